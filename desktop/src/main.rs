@@ -1,5 +1,13 @@
 use gb_core::cpu::Cpu;
-use sdl2::event::Event;
+use gb_core::joypad::Button;
+use gb_core::link;
+use gb_core::movie::{Movie, Player, Recorder};
+use gb_core::printer::Printer;
+use gb_core::rewind::Rewind;
+use gb_core::serial::{SerialLink, StdoutLink};
+use gb_core::symbols::Symbols;
+use gb_core::trace::Tracer;
+use sdl2::event::{Event, WindowEvent};
 use sdl2::pixels::Color;
 use sdl2::rect::Rect;
 use sdl2::render::Canvas;
@@ -7,26 +15,107 @@ use sdl2::video::Window;
 use std::env;
 use std::fs::File;
 use std::io::Read;
-use sdl2::keyboard::Keycode;
+use std::path::Path;
+use sdl2::keyboard::{Keycode, Mod};
+
+mod font;
+mod repl;
+mod speed;
+mod viewer;
 
 const SCALE: u32 = 2;
 const SCREEN_WIDTH: usize = 160;
 const SCREEN_HEIGHT: usize = 144;
 const WINDOW_WIDTH: u32 = (SCREEN_WIDTH as u32) * SCALE;
 const WINDOW_HEIGHT: u32 = (SCREEN_HEIGHT as u32) * SCALE;
+const REWIND_INTERVAL: u32 = 2; // Frames between rewind snapshots.
 
 // Gets input rom path and starts main loop
+// Usage: desktop [rom] [--link-listen ADDR | --link-connect ADDR | --printer DIR] [--rewind-mb N] [--debug]
+//        [--symbols FILE] [--ff-speed N]
+// ADDR is host:port, or unix:PATH for a Unix socket. --printer saves printouts as PNGs in DIR.
+// --rewind-mb sets how much memory the rewind history (hold Backspace) may use, 64 MiB by default.
+// --record FILE records input to a movie file until the window is closed, --play FILE replays one.
+// --debug starts paused with a debugger console on stdin.
+// --trace FILE logs every instruction in the Gameboy Doctor format, and --trace-diff FILE stops at
+// the first instruction that doesn't match a reference log. --doctor makes LY always read 0x90, as
+// Gameboy Doctor reference logs expect.
+// --symbols FILE loads labels for the debugger from an RGBDS .sym file. Without it, the .sym file
+// next to the rom is used if there is one.
+// Hold Tab to fast-forward, as fast as possible or at N times normal speed with --ff-speed N. S
+// switches between full, half and quarter speed, P pauses and N runs a single frame.
+// Keys 1-5 toggle debug windows showing the tiles, background maps, OAM, palettes and memory. Space
+// in the tile window changes the palette the tiles are drawn with. In the memory window, the arrow
+// keys, Page Up and Page Down move the cursor, Home and End jump between regions, and typing two
+// hex digits changes the byte under the cursor.
 fn main() {
-    let mut args: Vec<_> = env::args().collect();
-    if args.len() != 2 {
-        args = vec![String::from(""), String::from("./tetris.gb")];
+    let mut rom_path = String::from("./tetris.gb");
+    let mut serial: Box<dyn SerialLink> = Box::new(StdoutLink::new(true));
+    let mut rewind_mb = 64;
+    let mut record_path = None;
+    let mut play_path = None;
+    let mut debug = false;
+    let mut trace_path = None;
+    let mut trace_diff_path = None;
+    let mut symbols_path = None;
+    let mut fast_forward_speed = None;
+    let mut doctor = false;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--link-listen" | "--link-connect" => {
+                let addr = args.next().expect("Missing link address");
+                println!("Waiting for link cable on {}", addr);
+                serial = open_link(&addr, arg == "--link-listen").expect("Unable to open link cable");
+            }
+            "--printer" => {
+                let dir = args.next().expect("Missing printer output directory");
+                serial = Box::new(Printer::new(dir));
+            }
+            "--rewind-mb" => {
+                rewind_mb = args.next().and_then(|n| n.parse().ok()).expect("Invalid rewind memory size");
+            }
+            "--record" => record_path = Some(args.next().expect("Missing movie path")),
+            "--play" => play_path = Some(args.next().expect("Missing movie path")),
+            "--debug" => debug = true,
+            "--trace" => trace_path = Some(args.next().expect("Missing trace path")),
+            "--trace-diff" => trace_diff_path = Some(args.next().expect("Missing reference trace path")),
+            "--doctor" => doctor = true,
+            "--symbols" => symbols_path = Some(args.next().expect("Missing symbol file path")),
+            "--ff-speed" => {
+                fast_forward_speed = Some(args.next().and_then(|n| n.parse().ok()).filter(|&n| n > 0).expect("Invalid fast-forward speed"));
+            }
+            _ => rom_path = arg,
+        }
     }
 
     let mut gb = Cpu::new();
-    let mut rom = File::open(&args[1]).expect("Unable to open file");
+    let mut rom = File::open(&rom_path).expect("Unable to open file");
     let mut buffer = Vec::new();
     rom.read_to_end(&mut buffer).unwrap();
-    gb.load(&buffer);
+    if let Err(e) = gb.load(&buffer) {
+        println!("Unable to load {}: {}", rom_path, e);
+        std::process::exit(2);
+    }
+    gb.set_serial_link(serial);
+    gb.set_doctor_mode(doctor);
+    let symbols = match symbols_path {
+        Some(path) => Some(Symbols::load(Path::new(&path)).expect("Unable to load symbols")),
+        None => Symbols::load_for_rom(Path::new(&rom_path)).expect("Unable to load symbols"),
+    };
+    if trace_path.is_some() || trace_diff_path.is_some() {
+        let tracer = Tracer::open(trace_path.as_deref().map(Path::new), trace_diff_path.as_deref().map(Path::new));
+        let mut tracer = tracer.expect("Unable to open trace");
+        tracer.set_symbols(symbols.clone());
+        gb.set_tracer(Some(tracer));
+    }
+
+    let mut recorder = record_path.as_ref().map(|_| Recorder::new(&gb));
+    let mut player = play_path.map(|path| {
+        let data = std::fs::read(&path).expect("Unable to open movie");
+        let movie = Movie::from_bytes(&data).expect("Invalid movie");
+        Player::new(movie, &mut gb).expect("Unable to play movie")
+    });
 
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
@@ -36,10 +125,15 @@ fn main() {
         .opengl()
         .build()
         .unwrap();
-    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
+    let mut canvas = window.into_canvas().build().unwrap();
     canvas.clear();
     canvas.present();
     let mut event_pump = sdl_context.event_pump().unwrap();
+    let mut rewind = Rewind::new(REWIND_INTERVAL, rewind_mb << 20);
+    let mut rewinding = false;
+    let mut repl = debug.then(|| repl::Repl::start(symbols.unwrap_or_default()));
+    let mut viewers: Vec<viewer::Viewer> = Vec::new();
+    let mut speed = speed::Speed::new(fast_forward_speed);
 
     'gameloop: loop {
         for evt in event_pump.poll_iter() {
@@ -47,46 +141,204 @@ fn main() {
                 Event::Quit { .. } | Event::KeyDown{keycode: Some(Keycode::Escape), ..} => {
                     break 'gameloop;
                 },
-                Event::KeyDown{keycode: Some(key), ..} => {
-                    if let Some((key, select)) = key_code((key)) {
-                        gb.mmu.joypad.select = select;
-                        gb.mmu.joypad.up(key);
+                Event::KeyDown{keycode: Some(Keycode::Backspace), ..} if recorder.is_some() || player.is_some() => (),
+                Event::KeyDown{keycode: Some(Keycode::Backspace), ..} => rewinding = true,
+                Event::KeyUp{keycode: Some(Keycode::Backspace), ..} => rewinding = false,
+                Event::Window{window_id, win_event: WindowEvent::Close, ..} => {
+                    viewers.retain(|v| v.window_id() != window_id);
+                },
+                Event::KeyDown{keycode: Some(key), window_id, ..} if viewers.iter().any(|v| v.window_id() == window_id && v.handles_key(key)) => {
+                    viewers.iter_mut().filter(|v| v.window_id() == window_id).for_each(|v| v.key(key, &mut gb.mmu));
+                },
+                Event::KeyDown{keycode: Some(key), ..} if debug_view(key).is_some() => {
+                    let view = debug_view(key).unwrap();
+                    if viewers.iter().any(|v| v.view() == view) {
+                        viewers.retain(|v| v.view() != view);
+                    } else {
+                        match viewer::Viewer::open(&video_subsystem, view) {
+                            Ok(viewer) => viewers.push(viewer),
+                            Err(e) => println!("Unable to open debug window: {}", e),
+                        }
+                    }
+                },
+                Event::KeyDown{keycode: Some(Keycode::Tab), ..} => speed.set_fast_forward(true),
+                Event::KeyUp{keycode: Some(Keycode::Tab), ..} => speed.set_fast_forward(false),
+                Event::KeyDown{keycode: Some(Keycode::S), repeat: false, ..} => speed.next_slow_motion(),
+                Event::KeyDown{keycode: Some(Keycode::P), repeat: false, ..} => speed.toggle_pause(),
+                Event::KeyDown{keycode: Some(Keycode::N), ..} => speed.advance_frame(),
+                _ if player.is_some() => (),
+                Event::KeyDown{keycode: Some(key), keymod, ..} => {
+                    if let Some(slot) = save_slot(key) {
+                        let path = format!("{}.ss{}", rom_path, slot);
+                        if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                            match std::fs::write(&path, gb.save_state()) {
+                                Ok(()) => println!("Saved state to slot {}", slot),
+                                Err(e) => println!("Unable to save {}: {}", path, e),
+                            }
+                        } else if recorder.is_some() {
+                            println!("Can't load a state while recording a movie");
+                        } else {
+                            match std::fs::read(&path).map_err(|e| e.to_string()).and_then(|data| gb.load_state(&data).map_err(|e| e.to_string())) {
+                                Ok(()) => println!("Loaded state from slot {}", slot),
+                                Err(e) => println!("Unable to load {}: {}", path, e),
+                            }
+                        }
+                    }
+                    if let Some(button) = key_code(key) {
+                        gb.set_button(button, true);
                     }
                 },
                 Event::KeyUp{keycode: Some(key), ..} => {
-                    if let Some((key, select)) = key_code((key)) {
-                        gb.mmu.joypad.select = select;
-                        gb.mmu.joypad.down(key);
+                    if let Some(button) = key_code(key) {
+                        gb.set_button(button, false);
                     }
                 },
                 _ => (),
             }
         }
 
-        gb.do_cycle();
+        if rewinding {
+            // This steps back one snapshot per frame, at the current speed.
+            if rewind.step_back(&mut gb) {
+                draw_screen(&gb, &mut canvas, speed.label());
+                speed.wait();
+                continue;
+            }
+            rewinding = false;
+        }
+
+        // Run a whole frame between polling for input, which movies also rely on. The result is
+        // whether a frame was finished, rather than the debugger or a movie stopping first.
+        let result = if !speed.run_frame() {
+            Ok(false)
+        } else if let Some(recorder) = recorder.as_mut() {
+            recorder.run_frame(&mut gb).map(|_| true)
+        } else if let Some(movie) = player.as_mut() {
+            match movie.run_frame(&mut gb) {
+                Ok(Some(_)) => Ok(true),
+                Ok(None) => {
+                    println!("Movie finished after {} frames", movie.frame());
+                    player = None;
+                    Ok(false)
+                }
+                Err(e) => Err(e),
+            }
+        } else if let Some(repl) = repl.as_mut() {
+            Ok(repl.run_frame(&mut gb))
+        } else {
+            gb.run_frame().map(|_| true)
+        };
+        let frame_finished = match result {
+            Ok(frame_finished) => frame_finished,
+            Err(e) if e.is_fatal() => {
+                println!("Emulation stopped: {}", e);
+                break 'gameloop;
+            }
+            // The rest of the frame runs next time round.
+            Err(e) => {
+                println!("{}", e);
+                false
+            }
+        };
+        // The debugger reports divergences itself, otherwise there's nothing left to do but stop.
+        if repl.is_none() {
+            if let Some(divergence) = gb.tracer().and_then(|t| t.divergence()) {
+                println!("{}", divergence);
+                break 'gameloop;
+            }
+        }
+        if frame_finished {
+            rewind.push_frame(&gb);
+        }
         // TODO: Run renderer on seperate thread.
-        if gb.ppu_updated() {
-            draw_screen(&gb, &mut canvas)
+        if speed.draw_frame() {
+            draw_screen(&gb, &mut canvas, speed.label());
+            for viewer in viewers.iter_mut() {
+                if let Err(e) = viewer.draw(&gb.mmu) {
+                    println!("Unable to draw debug window: {}", e);
+                }
+            }
         }
+        speed.wait();
+    }
+
+    if let Some(mut tracer) = gb.take_tracer() {
+        if let Some(e) = tracer.error() {
+            println!("Trace stopped early: {}", e);
+        }
+        if let Err(e) = tracer.flush() {
+            println!("Unable to write trace: {}", e);
+        }
+    }
+
+    if let (Some(recorder), Some(path)) = (recorder, record_path) {
+        if let Err(e) = std::fs::write(&path, recorder.finish().to_bytes()) {
+            println!("Unable to save movie {}: {}", path, e);
+        }
+    }
+}
+
+fn open_link(addr: &str, listen: bool) -> std::io::Result<Box<dyn SerialLink>> {
+    if let Some(path) = addr.strip_prefix("unix:") {
+        return open_unix_link(path, listen);
+    }
+    Ok(if listen { Box::new(link::tcp_listen(addr)?) } else { Box::new(link::tcp_connect(addr)?) })
+}
+
+#[cfg(unix)]
+fn open_unix_link(path: &str, listen: bool) -> std::io::Result<Box<dyn SerialLink>> {
+    Ok(if listen { Box::new(link::unix_listen(path)?) } else { Box::new(link::unix_connect(path)?) })
+}
+
+#[cfg(not(unix))]
+fn open_unix_link(_path: &str, _listen: bool) -> std::io::Result<Box<dyn SerialLink>> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "unix sockets aren't supported on this platform"))
+}
+
+// F1-F10 load from save slots 1-10, and save to them while Shift is held.
+fn save_slot(key: Keycode) -> Option<u32> {
+    match key {
+        Keycode::F1 => Some(1),
+        Keycode::F2 => Some(2),
+        Keycode::F3 => Some(3),
+        Keycode::F4 => Some(4),
+        Keycode::F5 => Some(5),
+        Keycode::F6 => Some(6),
+        Keycode::F7 => Some(7),
+        Keycode::F8 => Some(8),
+        Keycode::F9 => Some(9),
+        Keycode::F10 => Some(10),
+        _ => None,
     }
 }
 
-fn key_code(key: Keycode) -> Option<(u8, bool)> {
+fn debug_view(key: Keycode) -> Option<viewer::View> {
     match key {
-        Keycode::Right => Some((0, false)),
-        Keycode::Left => Some((1, false)),
-        Keycode::Up => Some((2, false)),
-        Keycode::Down => Some((3, false)),
-
-        Keycode::C => Some((0, true)), // This is actually A (on the gameboy), but the C key is a nicer keybind for a keyboard.
-        Keycode::X => Some((1, true)), // Actually B
-        Keycode::Q => Some((2, true)), // Actually Start
-        Keycode::W => Some((3, true)), // Actually Select
+        Keycode::Num1 => Some(viewer::View::Tiles),
+        Keycode::Num2 => Some(viewer::View::Maps),
+        Keycode::Num3 => Some(viewer::View::Oam),
+        Keycode::Num4 => Some(viewer::View::Palettes),
+        Keycode::Num5 => Some(viewer::View::Memory),
+        _ => None,
+    }
+}
+
+fn key_code(key: Keycode) -> Option<Button> {
+    match key {
+        Keycode::Right => Some(Button::Right),
+        Keycode::Left => Some(Button::Left),
+        Keycode::Up => Some(Button::Up),
+        Keycode::Down => Some(Button::Down),
+
+        Keycode::C => Some(Button::A), // The C key is a nicer keybind for A on a keyboard.
+        Keycode::X => Some(Button::B),
+        Keycode::W => Some(Button::Select),
+        Keycode::Q => Some(Button::Start),
         _ => {None}
     }
 }
 
-fn draw_screen(emu: &Cpu, canvas: &mut Canvas<Window>) {
+fn draw_screen(emu: &Cpu, canvas: &mut Canvas<Window>, label: Option<String>) {
     // Clear canvas as black
     canvas.set_draw_color(Color::RGB(0, 0, 0));
     canvas.clear();
@@ -103,5 +355,21 @@ fn draw_screen(emu: &Cpu, canvas: &mut Canvas<Window>) {
         let rect = Rect::new((x * SCALE) as i32, (y * SCALE) as i32, SCALE, SCALE);
         canvas.fill_rect(rect).unwrap();
     }
+    if let Some(label) = label {
+        draw_label(canvas, &label);
+    }
     canvas.present();
 }
+
+// Draws the speed indicator in the top right corner, as white text on black.
+fn draw_label(canvas: &mut Canvas<Window>, label: &str) {
+    let (width, height) = (font::width(label) + 1, font::HEIGHT + 2);
+    let left = SCREEN_WIDTH - width - 2;
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.fill_rect(Rect::new((left * SCALE as usize) as i32, 2 * SCALE as i32, width as u32 * SCALE, height as u32 * SCALE)).unwrap();
+    canvas.set_draw_color(Color::RGB(255, 255, 255));
+    font::draw(label, |x, y| {
+        let rect = Rect::new(((left + 1 + x) as u32 * SCALE) as i32, ((3 + y) as u32 * SCALE) as i32, SCALE, SCALE);
+        canvas.fill_rect(rect).unwrap();
+    });
+}
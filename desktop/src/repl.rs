@@ -0,0 +1,322 @@
+use gb_core::cpu::Cpu;
+use gb_core::debugger::{self, Access, Breakpoint, Debugger, Stop, Watchpoint};
+use gb_core::disasm;
+use gb_core::memview::{self, MemoryView};
+use gb_core::symbols::Symbols;
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+const HELP: &str = "Commands (addresses and values are hex or labels, frame numbers decimal):
+  c, continue          Resume running
+  p, pause             Stop running
+  s, step              Execute one instruction
+  n, next              Step over a CALL or RST
+  finish               Run until the current function returns
+  frame N              Run until frame N
+  b ADDR               Add a breakpoint, only in its own bank for labels in switchable ROM
+  d ADDR               Delete a breakpoint
+  w ADDR [r|w|rw] [=V] Add a watchpoint, optionally only for value V (write by default)
+  dw N                 Delete watchpoint N
+  info                 List breakpoints and watchpoints
+  r, regs              Show registers
+  set REG VALUE        Set a register (a, f, b, c, d, e, h, l, af, bc, de, hl, sp, pc)
+  dis [ADDR] [N]       Disassemble N instructions (10 by default) from ADDR or PC
+  x ADDR [LEN]         Show memory
+  mem ADDR [LEN]       Show memory whenever execution stops, highlighting changes (key 5 in the
+                       game window opens a live, editable view)
+  mem                  Show the memory panel again
+  mem off              Close the memory panel
+  poke ADDR VALUE...   Write memory, without side effects like bank switching";
+
+// Terminal codes to show changed bytes in reverse video.
+const HIGHLIGHT: (&str, &str) = ("\x1b[7m", "\x1b[0m");
+
+// A debugger console on stdin. Commands are read on their own thread so the window keeps
+// drawing and handling input while waiting for them.
+pub struct Repl {
+    commands: Receiver<String>,
+    debugger: Debugger,
+    symbols: Symbols,
+    memory: Option<MemoryView>,
+    paused: bool,
+}
+
+impl Repl {
+    // Starts paused, so breakpoints can be set before the game runs.
+    pub fn start(symbols: Symbols) -> Self {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                let Ok(line) = line else { break };
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        println!("Debugger ready, type help for a list of commands");
+        prompt();
+        Self { commands: rx, debugger: Debugger::new(), symbols, memory: None, paused: true }
+    }
+
+    // Handles pending commands, then runs a frame unless paused. Returns whether the whole frame
+    // ran, without pausing or execution stopping part way through.
+    pub fn run_frame(&mut self, gb: &mut Cpu) -> bool {
+        loop {
+            match self.commands.try_recv() {
+                Ok(line) => {
+                    self.command(gb, &line);
+                    if self.paused {
+                        prompt();
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => break,
+            }
+        }
+        if self.paused {
+            thread::sleep(Duration::from_millis(16));
+            return false;
+        }
+        let Some(stop) = self.debugger.run_frame(gb) else { return true };
+        self.paused = true;
+        match stop {
+            Stop::Breakpoint(addr) => println!("Breakpoint at {}", self.describe(gb, addr)),
+            Stop::Watchpoint(hit) => println!(
+                "Watchpoint: {} ${:02X} at {}",
+                if hit.write { "wrote" } else { "read" },
+                hit.value,
+                self.describe(gb, hit.addr)
+            ),
+            Stop::Step => (),
+            Stop::Frame(frame) => println!("Reached frame {}", frame),
+            Stop::Error(e) => println!("Stopped: {}", e),
+            Stop::Divergence => {
+                if let Some(divergence) = gb.tracer().and_then(|t| t.divergence()) {
+                    println!("{}", divergence);
+                }
+            }
+        }
+        self.print_registers(gb);
+        self.print_memory(gb);
+        prompt();
+        // Reaching a requested frame still finishes it.
+        matches!(stop, Stop::Frame(_))
+    }
+
+    fn command(&mut self, gb: &mut Cpu, line: &str) {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        let Some(&name) = args.first() else { return };
+        let result = match (name, &args[1..]) {
+            ("help" | "h" | "?", _) => {
+                println!("{}", HELP);
+                Ok(())
+            }
+            ("c" | "continue", []) => {
+                self.debugger.cancel();
+                self.paused = false;
+                Ok(())
+            }
+            ("p" | "pause", []) => {
+                self.debugger.cancel();
+                self.paused = true;
+                self.print_registers(gb);
+                self.print_memory(gb);
+                Ok(())
+            }
+            ("s" | "step", []) => self.resume(|d| d.step_into()),
+            ("n" | "next", []) => self.resume(|d| d.step_over(gb)),
+            ("finish", []) => self.resume(|d| d.step_out(gb)),
+            ("frame", [frame]) => match frame.parse() {
+                Ok(frame) => self.resume(|d| d.run_to_frame(frame)),
+                Err(_) => Err(format!("Invalid frame number {}", frame)),
+            },
+            ("b", [addr]) => self.parse_breakpoint(addr).map(|breakpoint| {
+                if !self.debugger.add_breakpoint(breakpoint) {
+                    println!("Already a breakpoint at {}", self.describe_breakpoint(gb, breakpoint));
+                }
+            }),
+            ("d", [addr]) => self.parse_breakpoint(addr).map(|breakpoint| {
+                if !self.debugger.remove_breakpoint(breakpoint) {
+                    println!("No breakpoint at {}", self.describe_breakpoint(gb, breakpoint));
+                }
+            }),
+            ("w", [addr, options @ ..]) => {
+                let watchpoint = self.parse_addr(addr).and_then(|addr| parse_watchpoint(addr, options));
+                watchpoint.map(|w| self.debugger.add_watchpoint(w))
+            }
+            ("dw", [index]) => match index.parse().ok().and_then(|i| self.debugger.remove_watchpoint(i)) {
+                Some(_) => Ok(()),
+                None => Err(format!("No watchpoint {}", index)),
+            },
+            ("info", []) => {
+                for &breakpoint in self.debugger.breakpoints() {
+                    println!("Breakpoint at {}", self.describe_breakpoint(gb, breakpoint));
+                }
+                for (i, w) in self.debugger.watchpoints().iter().enumerate() {
+                    let value = w.value.map_or(String::new(), |v| format!(" = ${:02X}", v));
+                    println!("Watchpoint {}: {:?} {}{}", i, w.access, self.describe(gb, w.addr), value);
+                }
+                Ok(())
+            }
+            ("r" | "regs", []) => {
+                self.print_registers(gb);
+                Ok(())
+            }
+            ("set", [register, value]) => self.parse_addr(value).and_then(|value| {
+                if debugger::set_register(gb, register, value) {
+                    Ok(())
+                } else {
+                    Err(format!("Unknown register {}", register))
+                }
+            }),
+            ("dis", args) if args.len() <= 2 => {
+                let addr = args.first().map_or(Ok(gb.pc()), |addr| self.parse_addr(addr));
+                let count = args.get(1).map_or(Ok(10), |count| count.parse().map_err(|_| format!("Invalid count {}", count)));
+                addr.and_then(|addr| count.map(|count| self.disassemble(gb, addr, count)))
+            }
+            ("x", [addr, len @ ..]) if len.len() <= 1 => {
+                let len = len.first().map_or(Ok(0x40), |len| parse_hex(len));
+                self.parse_addr(addr).and_then(|addr| {
+                    for row in memview::dump(&gb.mmu, addr, len? as usize) {
+                        println!("{}", row);
+                    }
+                    Ok(())
+                })
+            }
+            ("mem", ["off"]) => {
+                self.memory = None;
+                Ok(())
+            }
+            ("mem", []) if self.memory.is_some() => {
+                self.print_memory(gb);
+                Ok(())
+            }
+            ("mem", [addr, len @ ..]) if len.len() <= 1 => {
+                let len = len.first().map_or(Ok(0x40), |len| parse_hex(len));
+                self.parse_addr(addr).and_then(|addr| {
+                    self.memory = Some(MemoryView::new(addr, len? as usize));
+                    self.print_memory(gb);
+                    Ok(())
+                })
+            }
+            ("poke", [addr, values @ ..]) if !values.is_empty() => self.parse_addr(addr).and_then(|addr| {
+                let values = values.iter().map(|v| parse_hex(v)).collect::<Result<Vec<_>, _>>()?;
+                for (i, value) in values.into_iter().enumerate() {
+                    gb.mmu.poke(addr.wrapping_add(i as u16), value as u8);
+                }
+                Ok(())
+            }),
+            _ => Err(format!("Unknown command or wrong arguments: {}", line.trim())),
+        };
+        if let Err(e) = result {
+            println!("{}", e);
+        }
+    }
+
+    fn resume(&mut self, target: impl FnOnce(&mut Debugger)) -> Result<(), String> {
+        target(&mut self.debugger);
+        self.paused = false;
+        Ok(())
+    }
+
+    // A label from the symbol file, or a hex number.
+    fn parse_addr(&self, text: &str) -> Result<u16, String> {
+        match self.symbols.address(text) {
+            Some((_, addr)) => Ok(addr),
+            None => parse_hex(text),
+        }
+    }
+
+    // Labels in switchable ROM banks only break in their own bank. Everything else breaks
+    // whatever is mapped, since RGBDS puts labels for ROMs without banking in bank 0.
+    fn parse_breakpoint(&self, text: &str) -> Result<Breakpoint, String> {
+        match self.symbols.address(text) {
+            Some((bank, addr @ 0x4000..=0x7FFF)) if bank != 0 => Ok(Breakpoint::banked(addr, bank)),
+            _ => self.parse_addr(text).map(Breakpoint::at),
+        }
+    }
+
+    fn describe_breakpoint(&self, gb: &Cpu, breakpoint: Breakpoint) -> String {
+        match breakpoint.bank {
+            Some(bank) => match self.symbols.locate(bank, breakpoint.addr) {
+                Some(location) => format!("${:02X}:{:04X} ({})", bank, breakpoint.addr, location),
+                None => format!("${:02X}:{:04X}", bank, breakpoint.addr),
+            },
+            None => self.describe(gb, breakpoint.addr),
+        }
+    }
+
+    // Formats an address along with the closest label before it, like $4A31 (Main+$2).
+    fn describe(&self, gb: &Cpu, addr: u16) -> String {
+        match self.symbols.locate_live(&gb.mmu, addr) {
+            Some(location) => format!("${:04X} ({})", addr, location),
+            None => format!("${:04X}", addr),
+        }
+    }
+
+    fn print_memory(&mut self, gb: &Cpu) {
+        if let Some(memory) = self.memory.as_mut() {
+            for row in memory.refresh(&gb.mmu) {
+                println!("{}", row.text(HIGHLIGHT));
+            }
+        }
+    }
+
+    fn print_registers(&self, gb: &Cpu) {
+        let r = gb.registers();
+        println!(
+            "AF={:04X} BC={:04X} DE={:04X} HL={:04X} SP={:04X} PC={:04X} IME={}",
+            r.get_af(),
+            r.get_bc(),
+            r.get_de(),
+            r.get_hl(),
+            gb.sp(),
+            gb.pc(),
+            gb.ime() as u8
+        );
+        self.disassemble(gb, gb.pc(), 1);
+    }
+
+    fn disassemble(&self, gb: &Cpu, mut addr: u16, count: usize) {
+        for _ in 0..count {
+            if let Some(label) = self.symbols.label(&gb.mmu, addr) {
+                println!("{}:", label);
+            }
+            let instruction = disasm::decode_live(gb, addr);
+            let bytes: Vec<String> =
+                (0..instruction.length as u16).map(|i| format!("{:02X}", gb.mmu.peek(addr.wrapping_add(i)))).collect();
+            let text = instruction.text_with(|target| self.symbols.label(&gb.mmu, target).map(String::from));
+            println!("{:04X}: {:9} {}", addr, bytes.join(" "), text);
+            addr = addr.wrapping_add(instruction.length as u16);
+        }
+    }
+}
+
+fn prompt() {
+    print!("(gebb) ");
+    io::stdout().flush().ok();
+}
+
+// Accepts $1234, 0x1234 or plain 1234, all as hex.
+fn parse_hex(text: &str) -> Result<u16, String> {
+    let digits = text.strip_prefix('$').or_else(|| text.strip_prefix("0x")).unwrap_or(text);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("Invalid number {}", text))
+}
+
+fn parse_watchpoint(addr: u16, options: &[&str]) -> Result<Watchpoint, String> {
+    let mut watchpoint = Watchpoint { addr, access: Access::Write, value: None };
+    for option in options {
+        match *option {
+            "r" => watchpoint.access = Access::Read,
+            "w" => watchpoint.access = Access::Write,
+            "rw" => watchpoint.access = Access::Any,
+            _ => match option.strip_prefix('=') {
+                Some(value) => watchpoint.value = Some(parse_hex(value)? as u8),
+                None => return Err(format!("Unknown watchpoint option {}", option)),
+            },
+        }
+    }
+    Ok(watchpoint)
+}
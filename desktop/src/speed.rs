@@ -0,0 +1,175 @@
+use gb_core::cpu::TICKS_PER_FRAME;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CLOCK_HZ: f64 = 4_194_304.0;
+const SLOW_MOTION: [f64; 3] = [1.0, 0.5, 0.25];
+
+// Real time taken by one frame on hardware, about 16.7ms.
+fn frame_time() -> Duration {
+    Duration::from_secs_f64(TICKS_PER_FRAME as f64 / CLOCK_HZ)
+}
+
+// Paces the main loop: how often frames run, and whether they run at all. Fast-forward runs at a
+// multiple of normal speed, or as fast as possible, while its key is held. Slow motion, pausing
+// and single frame advance are toggled with their own keys.
+pub struct Speed {
+    fast_forward: bool,
+    // None for uncapped.
+    fast_forward_speed: Option<u32>,
+    slow_motion: usize,
+    paused: bool,
+    // Frames to run while paused.
+    advance: u32,
+    next_frame: Instant,
+    last_draw: Instant,
+}
+
+impl Speed {
+    pub fn new(fast_forward_speed: Option<u32>) -> Self {
+        let now = Instant::now();
+        Self {
+            fast_forward: false,
+            fast_forward_speed,
+            slow_motion: 0,
+            paused: false,
+            advance: 0,
+            next_frame: now,
+            last_draw: now,
+        }
+    }
+
+    pub fn set_fast_forward(&mut self, held: bool) {
+        self.fast_forward = held;
+    }
+
+    // Goes from full speed to half, then quarter speed, then back again.
+    pub fn next_slow_motion(&mut self) {
+        self.slow_motion = (self.slow_motion + 1) % SLOW_MOTION.len();
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.advance = 0;
+    }
+
+    // Pauses if running, and runs one more frame.
+    pub fn advance_frame(&mut self) {
+        self.paused = true;
+        self.advance += 1;
+    }
+
+    // Whether the next frame should run, rather than just redrawing the screen.
+    pub fn run_frame(&mut self) -> bool {
+        if !self.paused {
+            return true;
+        }
+        if self.advance > 0 {
+            self.advance -= 1;
+            return true;
+        }
+        false
+    }
+
+    // How many times normal speed frames run at, or None if as fast as possible.
+    fn multiplier(&self) -> Option<f64> {
+        if self.paused {
+            Some(1.0)
+        } else if self.fast_forward {
+            self.fast_forward_speed.map(f64::from)
+        } else {
+            Some(SLOW_MOTION[self.slow_motion])
+        }
+    }
+
+    // Whether to draw this frame. Drawing is skipped when frames run faster than the display can
+    // show them.
+    pub fn draw_frame(&mut self) -> bool {
+        let now = Instant::now();
+        if self.multiplier().is_some_and(|m| m <= 1.0) || now - self.last_draw >= frame_time() {
+            self.last_draw = now;
+            return true;
+        }
+        false
+    }
+
+    // Sleeps until the next frame is due. If the emulator has fallen behind, such as after being
+    // uncapped, it carries on from now rather than rushing to catch up.
+    pub fn wait(&mut self) {
+        let Some(multiplier) = self.multiplier() else {
+            self.next_frame = Instant::now();
+            return;
+        };
+        self.next_frame += frame_time().div_f64(multiplier);
+        let now = Instant::now();
+        if self.next_frame > now {
+            thread::sleep(self.next_frame - now);
+        } else if now - self.next_frame > frame_time() {
+            self.next_frame = now;
+        }
+    }
+
+    // What the on-screen indicator shows, or None at normal speed.
+    pub fn label(&self) -> Option<String> {
+        if self.paused {
+            return Some(String::from("||"));
+        }
+        match self.multiplier() {
+            None => Some(String::from(">>")),
+            Some(m) if m > 1.0 => Some(format!(">>{}X", m)),
+            Some(m) if m < 1.0 => Some(format!("{}X", m)),
+            Some(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn paused_frames_only_run_when_advanced() {
+        let mut speed = Speed::new(None);
+        assert!(speed.run_frame());
+        speed.advance_frame();
+        speed.advance_frame();
+        assert!(speed.run_frame());
+        assert!(speed.run_frame());
+        assert!(!speed.run_frame());
+        speed.toggle_pause();
+        assert!(speed.run_frame());
+    }
+
+    #[test]
+    fn labels() {
+        let mut speed = Speed::new(Some(4));
+        assert_eq!(speed.label(), None);
+        assert_eq!(speed.multiplier(), Some(1.0));
+        speed.set_fast_forward(true);
+        assert_eq!(speed.label().as_deref(), Some(">>4X"));
+        assert_eq!(speed.multiplier(), Some(4.0));
+        speed.toggle_pause();
+        assert_eq!(speed.label().as_deref(), Some("||"));
+        assert_eq!(speed.multiplier(), Some(1.0));
+
+        let mut speed = Speed::new(None);
+        speed.set_fast_forward(true);
+        assert_eq!(speed.label().as_deref(), Some(">>"));
+        assert_eq!(speed.multiplier(), None);
+        speed.set_fast_forward(false);
+        speed.next_slow_motion();
+        assert_eq!(speed.label().as_deref(), Some("0.5X"));
+        assert_eq!(speed.multiplier(), Some(0.5));
+    }
+
+    #[test]
+    fn slow_motion_wraps_around() {
+        let mut speed = Speed::new(None);
+        speed.next_slow_motion();
+        speed.next_slow_motion();
+        assert_eq!(speed.label().as_deref(), Some("0.25X"));
+        speed.next_slow_motion();
+        assert_eq!(speed.label(), None);
+        assert_eq!(speed.multiplier(), Some(1.0));
+    }
+}
@@ -0,0 +1,371 @@
+use crate::font;
+use gb_core::cpu::mmu::MMU;
+use gb_core::memview::MemoryView;
+use gb_core::ppu::PPU;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+use sdl2::VideoSubsystem;
+
+const MAP_GAP: usize = 8;
+const VIEWPORT_COLOUR: (u8, u8, u8) = (255, 0, 0);
+const TEXT_COLOUR: (u8, u8, u8) = (0, 0, 160);
+const CHANGED_COLOUR: (u8, u8, u8) = (220, 0, 0);
+const CURSOR_COLOUR: (u8, u8, u8) = (255, 230, 120);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum View {
+    Tiles,
+    Maps,
+    Oam,
+    Palettes,
+    Memory,
+}
+
+impl View {
+    fn title(self) -> &'static str {
+        match self {
+            View::Tiles => "Tiles",
+            View::Maps => "Background maps",
+            View::Oam => "OAM (Y, X, tile, attributes)",
+            View::Palettes => "Palettes (BGP, OBP0, OBP1)",
+            View::Memory => "Memory",
+        }
+    }
+
+    // Size of the image drawn, and how much it's scaled up in the window.
+    fn size(self) -> (usize, usize, u32) {
+        match self {
+            View::Tiles => (16 * 8, 24 * 8, 3),
+            View::Maps => (256 * 2 + MAP_GAP, 256, 2),
+            View::Oam => (8 * OAM_CELL_WIDTH, 5 * OAM_CELL_HEIGHT, 3),
+            View::Palettes => (4 * 16 + 16, 3 * 16, 4),
+            View::Memory => (MEMORY_ASCII_X + 16 * font::ADVANCE + 2, MEMORY_ROWS * MEMORY_LINE + 2, 3),
+        }
+    }
+}
+
+// The palette tiles are decoded with, which Space cycles through in the tile window.
+#[derive(Clone, Copy)]
+enum TilePalette {
+    Grey,
+    Bgp,
+    Obp0,
+    Obp1,
+}
+
+// An RGB image drawn by a view before it's copied to the window.
+struct Image {
+    width: usize,
+    pixels: Vec<u8>,
+}
+
+impl Image {
+    fn new(width: usize, height: usize) -> Self {
+        Self { width, pixels: vec![0x40; width * height * 3] }
+    }
+
+    fn set(&mut self, x: usize, y: usize, (r, g, b): (u8, u8, u8)) {
+        let offset = (y * self.width + x) * 3;
+        self.pixels[offset..offset + 3].copy_from_slice(&[r, g, b]);
+    }
+
+    fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, colour: (u8, u8, u8)) {
+        for dy in 0..height {
+            for dx in 0..width {
+                self.set(x + dx, y + dy, colour);
+            }
+        }
+    }
+
+    fn text(&mut self, x: usize, y: usize, text: &str, colour: (u8, u8, u8)) {
+        font::draw(text, |dx, dy| self.set(x + dx, y + dy, colour));
+    }
+
+    // Draws a byte as two hex digits.
+    fn hex(&mut self, x: usize, y: usize, value: u8, colour: (u8, u8, u8)) {
+        self.text(x, y, &format!("{:02X}", value), colour);
+    }
+}
+
+// Each OAM entry gets a cell with its 8x16 preview on the left and its four bytes on the right.
+const OAM_CELL_WIDTH: usize = 22;
+const OAM_CELL_HEIGHT: usize = 26;
+
+// The memory window shows 16 rows of 16 bytes: the bank and address, the bytes in hex and then as
+// text.
+const MEMORY_ROWS: usize = 16;
+const MEMORY_LINE: usize = font::HEIGHT + 2;
+const MEMORY_HEX_X: usize = 2 + 8 * font::ADVANCE;
+const MEMORY_ASCII_X: usize = MEMORY_HEX_X + 16 * 3 * font::ADVANCE + font::ADVANCE;
+// Where Home and End jump to in the memory window.
+const MEMORY_REGIONS: [u16; 10] = [0x0000, 0x4000, 0x8000, 0xA000, 0xC000, 0xD000, 0xE000, 0xFE00, 0xFF00, 0xFF80];
+
+// The part of the address space the memory window shows, and the byte being edited. Typing two hex
+// digits writes the byte with MMU::poke, so edits have no side effects such as bank switching.
+struct MemoryCursor {
+    view: MemoryView,
+    addr: u16,
+    // The first digit typed for the byte at `addr`.
+    high: Option<u8>,
+}
+
+impl MemoryCursor {
+    fn new() -> Self {
+        Self { view: MemoryView::new(0xC000, MEMORY_ROWS * 16), addr: 0xC000, high: None }
+    }
+
+    // Moves to `addr`, scrolling to keep it on screen.
+    fn move_to(&mut self, addr: u16) {
+        self.addr = addr;
+        self.high = None;
+        let page = MEMORY_ROWS * 16;
+        let (start, row) = (self.view.start() as usize, addr as usize & !0xF);
+        let start = if row < start {
+            row
+        } else if row >= start + page {
+            row + 16 - page
+        } else {
+            return;
+        };
+        self.view = MemoryView::new(start.min(0x10000 - page) as u16, page);
+    }
+
+    fn key(&mut self, key: Keycode, mmu: &mut MMU) {
+        let page = (MEMORY_ROWS * 16) as u16;
+        match key {
+            Keycode::Left => self.move_to(self.addr.saturating_sub(1)),
+            Keycode::Right => self.move_to(self.addr.saturating_add(1)),
+            Keycode::Up => self.move_to(self.addr.checked_sub(16).unwrap_or(self.addr)),
+            Keycode::Down => self.move_to(self.addr.checked_add(16).unwrap_or(self.addr)),
+            Keycode::PageUp => self.move_to(self.addr.saturating_sub(page)),
+            Keycode::PageDown => self.move_to(self.addr.saturating_add(page)),
+            Keycode::Home => self.move_to(MEMORY_REGIONS.into_iter().rev().find(|&a| a < self.addr).unwrap_or(0)),
+            Keycode::End => self.move_to(MEMORY_REGIONS.into_iter().find(|&a| a > self.addr).unwrap_or(self.addr)),
+            _ => {
+                let Some(digit) = hex_digit(key) else { return };
+                match self.high.take() {
+                    None => self.high = Some(digit),
+                    Some(high) => {
+                        mmu.poke(self.addr, high << 4 | digit);
+                        self.move_to(self.addr.saturating_add(1));
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn hex_digit(key: Keycode) -> Option<u8> {
+    let name = key.name();
+    let name = name.strip_prefix("Keypad ").unwrap_or(&name);
+    match name.as_bytes() {
+        [c] => (*c as char).to_digit(16).map(|d| d as u8),
+        _ => None,
+    }
+}
+
+// A debug window showing part of the PPU's state or memory. The desktop opens one per view with the
+// number keys, and redraws them every frame.
+pub struct Viewer {
+    view: View,
+    canvas: Canvas<Window>,
+    palette: TilePalette,
+    memory: MemoryCursor,
+}
+
+impl Viewer {
+    pub fn open(video: &VideoSubsystem, view: View) -> Result<Self, String> {
+        let (width, height, scale) = view.size();
+        let window = video
+            .window(view.title(), width as u32 * scale, height as u32 * scale)
+            .build()
+            .map_err(|e| e.to_string())?;
+        // No vsync, so several windows don't slow down the main one.
+        let canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+        Ok(Self { view, canvas, palette: TilePalette::Grey, memory: MemoryCursor::new() })
+    }
+
+    pub fn view(&self) -> View {
+        self.view
+    }
+
+    pub fn window_id(&self) -> u32 {
+        self.canvas.window().id()
+    }
+
+    // Whether the window uses `key` itself, rather than it going to the game.
+    pub fn handles_key(&self, key: Keycode) -> bool {
+        match self.view {
+            View::Tiles => key == Keycode::Space,
+            View::Memory => {
+                hex_digit(key).is_some()
+                    || matches!(
+                        key,
+                        Keycode::Left
+                            | Keycode::Right
+                            | Keycode::Up
+                            | Keycode::Down
+                            | Keycode::PageUp
+                            | Keycode::PageDown
+                            | Keycode::Home
+                            | Keycode::End
+                    )
+            }
+            _ => false,
+        }
+    }
+
+    // Space in the tile window picks the next palette. In the memory window, the arrow keys, Page
+    // Up and Page Down move around, Home and End jump between regions and hex digits edit.
+    pub fn key(&mut self, key: Keycode, mmu: &mut MMU) {
+        match self.view {
+            View::Tiles if key == Keycode::Space => self.next_palette(),
+            View::Memory => self.memory.key(key, mmu),
+            _ => {}
+        }
+    }
+
+    fn next_palette(&mut self) {
+        let (palette, name) = match self.palette {
+            TilePalette::Grey => (TilePalette::Bgp, "BGP"),
+            TilePalette::Bgp => (TilePalette::Obp0, "OBP0"),
+            TilePalette::Obp0 => (TilePalette::Obp1, "OBP1"),
+            TilePalette::Obp1 => (TilePalette::Grey, "no palette"),
+        };
+        self.palette = palette;
+        self.canvas.window_mut().set_title(&format!("Tiles ({})", name)).ok();
+    }
+
+    pub fn draw(&mut self, mmu: &MMU) -> Result<(), String> {
+        let (width, height, _) = self.view.size();
+        let mut image = Image::new(width, height);
+        let ppu = &mmu.ppu;
+        match self.view {
+            View::Tiles => self.draw_tiles(ppu, &mut image),
+            View::Maps => draw_maps(ppu, &mut image),
+            View::Oam => draw_oam(ppu, &mut image),
+            View::Palettes => draw_palettes(ppu, &mut image),
+            View::Memory => draw_memory(&mut self.memory, mmu, &mut image),
+        }
+
+        let creator = self.canvas.texture_creator();
+        let mut texture =
+            creator.create_texture_static(PixelFormatEnum::RGB24, width as u32, height as u32).map_err(|e| e.to_string())?;
+        texture.update(None, &image.pixels, width * 3).map_err(|e| e.to_string())?;
+        self.canvas.copy(&texture, None, None)?;
+        self.canvas.present();
+        Ok(())
+    }
+
+    fn draw_tiles(&self, ppu: &PPU, image: &mut Image) {
+        let palette = match self.palette {
+            TilePalette::Grey => 0xE4,
+            TilePalette::Bgp => ppu.bgp(),
+            TilePalette::Obp0 => ppu.obp0(),
+            TilePalette::Obp1 => ppu.obp1(),
+        };
+        for tile in 0..384 {
+            draw_tile(ppu, image, tile, (tile % 16) * 8, (tile / 16) * 8, palette);
+        }
+    }
+}
+
+fn draw_tile(ppu: &PPU, image: &mut Image, tile: usize, x: usize, y: usize, palette: u8) {
+    for row in 0..8 {
+        for col in 0..8 {
+            let shade = PPU::shade(palette, ppu.tile_pixel(tile, col, row));
+            image.set(x + col, y + row, ppu.to_rgb(shade));
+        }
+    }
+}
+
+// Both maps side by side, with the part the screen shows outlined on the one LCDC selects.
+fn draw_maps(ppu: &PPU, image: &mut Image) {
+    for map in 0..2 {
+        let left = map * (256 + MAP_GAP);
+        for y in 0..32 {
+            for x in 0..32 {
+                draw_tile(ppu, image, ppu.map_tile(map, x, y), left + x * 8, y * 8, ppu.bgp());
+            }
+        }
+    }
+
+    let left = if ppu.lcdc() & 0b1000 != 0 { 256 + MAP_GAP } else { 0 };
+    let (scx, scy) = ppu.scroll();
+    // The viewport wraps around the edges of the map.
+    let mut plot = |x: usize, y: usize| image.set(left + (scx as usize + x) % 256, (scy as usize + y) % 256, VIEWPORT_COLOUR);
+    for x in 0..160 {
+        plot(x, 0);
+        plot(x, 143);
+    }
+    for y in 0..144 {
+        plot(0, y);
+        plot(159, y);
+    }
+}
+
+fn draw_oam(ppu: &PPU, image: &mut Image) {
+    let tall = ppu.lcdc() & 0b100 != 0;
+    for n in 0..40 {
+        let sprite = ppu.sprite(n);
+        let (x, y) = ((n % 8) * OAM_CELL_WIDTH, (n / 8) * OAM_CELL_HEIGHT);
+        image.fill(x + 1, y + 1, OAM_CELL_WIDTH - 2, OAM_CELL_HEIGHT - 2, (255, 255, 255));
+
+        let palette = if sprite.flags & 0x10 != 0 { ppu.obp1() } else { ppu.obp0() };
+        if tall {
+            draw_tile(ppu, image, (sprite.tile & 0xFE) as usize, x + 2, y + 4, palette);
+            draw_tile(ppu, image, (sprite.tile | 0x01) as usize, x + 2, y + 12, palette);
+        } else {
+            draw_tile(ppu, image, sprite.tile as usize, x + 2, y + 8, palette);
+        }
+        for (i, value) in [sprite.y, sprite.x, sprite.tile, sprite.flags].into_iter().enumerate() {
+            image.hex(x + 12, y + 2 + i * 6, value, TEXT_COLOUR);
+        }
+    }
+}
+
+// A row of swatches per palette, followed by the register's value.
+fn draw_palettes(ppu: &PPU, image: &mut Image) {
+    for (row, palette) in [ppu.bgp(), ppu.obp0(), ppu.obp1()].into_iter().enumerate() {
+        for colour in 0..4 {
+            let rgb = ppu.to_rgb(PPU::shade(palette, colour));
+            image.fill(colour as usize * 16 + 1, row * 16 + 1, 14, 14, rgb);
+        }
+        image.fill(4 * 16 + 1, row * 16 + 1, 14, 14, (255, 255, 255));
+        image.hex(4 * 16 + 4, row * 16 + 6, palette, TEXT_COLOUR);
+    }
+}
+
+// Bytes that changed since the last frame are shown in red, and the one being edited is highlighted.
+// Letters in the text column are all shown as capitals.
+fn draw_memory(cursor: &mut MemoryCursor, mmu: &MMU, image: &mut Image) {
+    let (width, height, _) = View::Memory.size();
+    image.fill(0, 0, width, height, (255, 255, 255));
+    for (row, line) in cursor.view.refresh(mmu).iter().enumerate() {
+        let y = 2 + row * MEMORY_LINE;
+        let addr = match line.bank {
+            Some(bank) => format!("{:02X}:{:04X}", bank, line.addr),
+            None => format!("{:04X}", line.addr),
+        };
+        image.text(2 + font::width("00:0000") - font::width(&addr), y, &addr, TEXT_COLOUR);
+        for (i, &byte) in line.bytes.iter().enumerate() {
+            let colour = if line.changed[i] { CHANGED_COLOUR } else { TEXT_COLOUR };
+            let (hex_x, ascii_x) = (MEMORY_HEX_X + i * 3 * font::ADVANCE, MEMORY_ASCII_X + i * font::ADVANCE);
+            let hex = if line.addr.wrapping_add(i as u16) == cursor.addr {
+                // Room for both digits, and the gap either side of them.
+                image.fill(hex_x - 2, y - 1, 2 * font::ADVANCE + 3, font::HEIGHT + 2, CURSOR_COLOUR);
+                match cursor.high {
+                    Some(high) => format!("{:X}.", high),
+                    None => format!("{:02X}", byte),
+                }
+            } else {
+                format!("{:02X}", byte)
+            };
+            image.text(hex_x, y, &hex, colour);
+            let c = if byte.is_ascii_alphanumeric() { byte.to_ascii_uppercase() as char } else { '.' };
+            image.text(ascii_x, y, &c.to_string(), colour);
+        }
+    }
+}
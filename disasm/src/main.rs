@@ -0,0 +1,169 @@
+use gb_core::disasm::{self, Instruction};
+use gb_core::symbols::Symbols;
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::process::ExitCode;
+
+const USAGE: &str = "Usage: gebb-disasm ROM [--bank N] [--symbols FILE]
+
+Disassembles every bank of ROM, or only bank N, to standard output. Labels come from the RGBDS
+.sym file FILE, or the one next to ROM if there is one. Other jump and call targets get labels
+named after their bank and address.";
+
+const BANK_SIZE: usize = 0x4000;
+
+// Sweeps a whole bank from start to end. Data is decoded as if it were code.
+fn sweep(rom: &[u8], bank: usize) -> Vec<Instruction> {
+    let (start, end) = if bank == 0 { (0, 0x4000) } else { (0x4000, 0x8000) };
+    let mut instructions = Vec::new();
+    let mut addr = start;
+    while addr < end {
+        let instruction = disasm::decode_rom(rom, bank, addr as u16);
+        addr += instruction.length as u32;
+        instructions.push(instruction);
+    }
+    instructions
+}
+
+// The bank a jump from `bank` to `target` lands in, if it can be known without running the game.
+fn target_bank(bank: usize, target: u16, banks: usize) -> Option<usize> {
+    match target {
+        0x0000..=0x3FFF => Some(0),
+        0x4000..=0x7FFF if bank != 0 => Some(bank),
+        0x4000..=0x7FFF if banks == 2 => Some(1),
+        _ => None,
+    }
+}
+
+// Symbols for the 0x4000 range of a ROM without banking are listed under bank 0.
+fn symbol(symbols: &Symbols, bank: usize, addr: u16, banks: usize) -> Option<&str> {
+    let bank0 = if banks == 2 { symbols.get(0, addr) } else { None };
+    symbols.get(bank as u16, addr).or(bank0)
+}
+
+fn label(symbols: &Symbols, bank: usize, addr: u16, banks: usize) -> String {
+    match symbol(symbols, bank, addr, banks) {
+        Some(name) => name.to_string(),
+        None => format!("L{:02X}_{:04X}", bank, addr),
+    }
+}
+
+fn write_listing(
+    out: &mut impl Write,
+    rom: &[u8],
+    banks: &[usize],
+    bank_count: usize,
+    symbols: &Symbols,
+) -> io::Result<()> {
+    let listings: Vec<(usize, Vec<Instruction>)> = banks.iter().map(|&bank| (bank, sweep(rom, bank))).collect();
+
+    // Only label targets that start an instruction in the sweep, otherwise there's nowhere to put them.
+    let starts: HashSet<(usize, u16)> =
+        listings.iter().flat_map(|(bank, list)| list.iter().map(move |i| (*bank, i.addr))).collect();
+    let mut labels: HashSet<(usize, u16)> = listings
+        .iter()
+        .flat_map(|(bank, list)| list.iter().map(move |i| (*bank, i)))
+        .filter(|(_, i)| i.mnemonic != "RST")
+        .filter_map(|(bank, i)| {
+            let target = i.target?;
+            Some((target_bank(bank, target, bank_count)?, target))
+        })
+        .filter(|target| starts.contains(target))
+        .collect();
+    labels.extend(starts.iter().filter(|(bank, addr)| symbol(symbols, *bank, *addr, bank_count).is_some()));
+
+    for (bank, list) in &listings {
+        writeln!(out, "; Bank ${:02X}", bank)?;
+        for i in list {
+            if labels.contains(&(*bank, i.addr)) {
+                writeln!(out, "\n{}:", label(symbols, *bank, i.addr, bank_count))?;
+            }
+            let bytes: Vec<String> = (0..i.length as u16)
+                .map(|offset| format!("{:02X}", disasm::rom_byte(rom, *bank, i.addr.wrapping_add(offset))))
+                .collect();
+            let text = i.text_with(|target| {
+                let target_bank = target_bank(*bank, target, bank_count)?;
+                labels.contains(&(target_bank, target)).then(|| label(symbols, target_bank, target, bank_count))
+            });
+            writeln!(out, "    {:04X}  {:9} {}", i.addr, bytes.join(" "), text)?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let mut args = env::args().skip(1);
+    let mut rom_path = None;
+    let mut bank = None;
+    let mut symbols_path = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--bank" => match args.next().and_then(|n| n.parse::<usize>().ok()) {
+                Some(n) => bank = Some(n),
+                None => {
+                    eprintln!("Invalid bank number\n\n{}", USAGE);
+                    return ExitCode::from(2);
+                }
+            },
+            "--symbols" => match args.next() {
+                Some(path) => symbols_path = Some(path),
+                None => {
+                    eprintln!("Missing symbol file path\n\n{}", USAGE);
+                    return ExitCode::from(2);
+                }
+            },
+            "-h" | "--help" => {
+                eprintln!("{}", USAGE);
+                return ExitCode::from(2);
+            }
+            _ => rom_path = Some(arg),
+        }
+    }
+    let Some(rom_path) = rom_path else {
+        eprintln!("Missing ROM path\n\n{}", USAGE);
+        return ExitCode::from(2);
+    };
+
+    let rom = match fs::read(&rom_path) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("Unable to open {}: {}", rom_path, e);
+            return ExitCode::from(2);
+        }
+    };
+    let symbols = match &symbols_path {
+        Some(path) => Symbols::load(Path::new(path)).map(Some),
+        None => Symbols::load_for_rom(Path::new(&rom_path)),
+    };
+    let symbols = match symbols {
+        Ok(symbols) => symbols.unwrap_or_default(),
+        Err(e) => {
+            eprintln!("Unable to load symbols: {}", e);
+            return ExitCode::from(2);
+        }
+    };
+    let bank_count = rom.len().div_ceil(BANK_SIZE).max(2);
+    let banks: Vec<usize> = match bank {
+        Some(bank) if bank >= bank_count => {
+            eprintln!("The ROM only has {} banks", bank_count);
+            return ExitCode::from(2);
+        }
+        Some(bank) => vec![bank],
+        None => (0..bank_count).collect(),
+    };
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    match write_listing(&mut out, &rom, &banks, bank_count, &symbols).and_then(|_| out.flush()) {
+        Ok(()) => ExitCode::SUCCESS,
+        // Piping into head and the like closes stdout early, which isn't an error.
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::from(2)
+        }
+    }
+}
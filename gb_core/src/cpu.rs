@@ -1,22 +1,33 @@
-mod registers;
-use core::panic;
+pub mod bus;
+pub mod registers;
 
 use registers::*;
-mod mmu;
+pub mod mmu;
 use mmu::*;
-
-const LOG_LEVEL: usize = 2;
-
-pub struct Cpu {
+use bus::Bus;
+use crate::error::Error;
+use crate::joypad::Button;
+use crate::serial::SerialLink;
+use crate::state::{self, StateError, StateReader, StateWriter};
+use crate::trace::Tracer;
+use std::sync::OnceLock;
+
+// Clock ticks in a frame on real hardware: 154 lines of 456 ticks each, about 59.7 frames a second.
+pub const TICKS_PER_FRAME: u32 = 70224;
+
+pub struct Cpu<B: Bus = MMU> {
     reg: Registers,
     pc: u16,
     sp: u16,
     ime: bool,
     tempIme: bool,
-    pub mmu: MMU,
+    pub mmu: B,
     cycle: usize,
-    line: usize,
-    debug_file: Vec<String>,
+    tracer: Option<Tracer>,
+    // Set when execute hits an opcode it doesn't implement.
+    error: Option<Error>,
+    // Ticks run since the last frame ended, for timing frames while the LCD is off.
+    frame_ticks: u32,
     halted: bool,
     setdi: u32,
     setei: u32,
@@ -24,42 +35,287 @@ pub struct Cpu {
 
 impl Cpu {
     pub fn new() -> Self {
-        let mut temp = Self {
+        Self::with_bus(MMU::new())
+    }
+
+    pub fn load(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.mmu.load(data)
+    }
+    
+    // Snapshots the whole machine: CPU, memory, PPU, timer, serial port and cartridge RAM/banking.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        w.bytes(state::MAGIC);
+        w.u32(state::VERSION);
+        w.u32(self.mmu.rom_checksum());
+        self.reg.save_state(&mut w);
+        w.u16(self.pc);
+        w.u16(self.sp);
+        w.bool(self.ime);
+        w.bool(self.tempIme);
+        w.bool(self.halted);
+        w.u32(self.setdi);
+        w.u32(self.setei);
+        w.u64(self.cycle as u64);
+        w.u32(self.frame_ticks);
+        self.mmu.save_state(&mut w);
+        w.finish()
+    }
+
+    // Restores a snapshot made by save_state. If the state is rejected for any reason, whether its
+    // size, ROM or version or a corrupt value found part way through, the machine is left exactly as
+    // it was.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut r = StateReader::new(data);
+        self.read_state_header(&mut r, data.len())?;
+        // Some values, like the timer rate, are only checked once the state before them has been
+        // read in, so keep a copy of the running machine to go back to.
+        let backup = self.save_state();
+        self.read_state(&mut r).or_else(|e| {
+            let mut r = StateReader::new(&backup);
+            self.read_state_header(&mut r, backup.len()).expect("Backup state has a bad header");
+            self.read_state(&mut r).expect("Backup state can't be loaded");
+            Err(e)
+        })
+    }
+
+    fn read_state_header(&self, r: &mut StateReader, len: usize) -> Result<(), StateError> {
+        let mut magic = [0; 4];
+        r.bytes(&mut magic)?;
+        if &magic != state::MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let version = r.u32()?;
+        if version != state::VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let expected = self.mmu.rom_checksum();
+        let found = r.u32()?;
+        if found != expected {
+            return Err(StateError::WrongRom { expected, found });
+        }
+        if len != state_size() {
+            return Err(StateError::WrongSize);
+        }
+        Ok(())
+    }
+
+    fn read_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.reg.load_state(r)?;
+        self.pc = r.u16()?;
+        self.sp = r.u16()?;
+        self.ime = r.bool()?;
+        self.tempIme = r.bool()?;
+        self.halted = r.bool()?;
+        self.setdi = r.u32()?;
+        self.setei = r.u32()?;
+        self.cycle = r.u64()? as usize;
+        self.frame_ticks = r.u32()?;
+        self.mmu.load_state(r)
+    }
+
+    // Plugs a peripheral or cable into the link port.
+    pub fn set_serial_link(&mut self, link: Box<dyn SerialLink>) {
+        self.mmu.serial.set_link(link);
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        self.mmu.joypad.set(button, pressed);
+    }
+
+    // Sets every button at once from a mask of `Button::mask` bits.
+    pub fn set_buttons(&mut self, mask: u8) {
+        self.mmu.joypad.set_all(mask);
+    }
+
+    // Mask of the buttons currently held, in the format taken by set_buttons.
+    pub fn buttons(&self) -> u8 {
+        self.mmu.joypad.held()
+    }
+
+    // Makes LY always read 0x90, the first line of VBlank, like the emulators Gameboy Doctor
+    // reference logs are made with. Without it a trace diverges at the first LY poll.
+    pub fn set_doctor_mode(&mut self, enabled: bool) {
+        self.mmu.set_ly_stub(enabled.then_some(0x90));
+    }
+
+    // Whether Left+Right and Up+Down are hidden from the game. On by default.
+    pub fn set_dpad_filter(&mut self, enabled: bool) {
+        self.mmu.joypad.filter_opposite = enabled;
+    }
+
+    pub fn dpad_filter(&self) -> bool {
+        self.mmu.joypad.filter_opposite
+    }
+
+    pub fn get_display(&self) -> &[u8] {
+        &self.mmu.ppu.screen_buffer.as_ref()
+    }
+
+    // Runs one frame and returns the number of ticks that took, which is TICKS_PER_FRAME give or
+    // take an instruction. A frame ends when the PPU enters VBlank, or while the LCD is off, once
+    // TICKS_PER_FRAME ticks have passed since the last one.
+    // Frontends can call this once per displayed frame and show the screen after it returns.
+    // It also returns early when the tracer diverges, right after the instruction that did.
+    pub fn run_frame(&mut self) -> Result<u32, Error> {
+        let diverged = self.diverged();
+        let mut ticks = 0;
+        loop {
+            let (cycle_ticks, frame_ended) = self.frame_cycle()?;
+            ticks += cycle_ticks;
+            if frame_ended || self.diverged() != diverged {
+                return Ok(ticks);
+            }
+        }
+    }
+
+    // Runs whole instructions until at least `ticks` ticks have passed, and returns how many did.
+    // That can be a few more than asked for, which callers keeping to a budget should carry over.
+    // Like run_frame, it returns early when the tracer diverges.
+    pub fn run_cycles(&mut self, ticks: u32) -> Result<u32, Error> {
+        let diverged = self.diverged();
+        let mut ran = 0;
+        while ran < ticks {
+            ran += self.frame_cycle()?.0;
+            if self.diverged() != diverged {
+                break;
+            }
+        }
+        Ok(ran)
+    }
+
+    fn diverged(&self) -> bool {
+        self.tracer.as_ref().is_some_and(|t| t.divergence().is_some())
+    }
+
+    // do_cycle, also returning whether that ended a frame.
+    pub(crate) fn frame_cycle(&mut self) -> Result<(u32, bool), Error> {
+        let was_vblank = self.mmu.ppu.mode() == 1;
+        let ticks = self.do_cycle()?;
+        self.frame_ticks += ticks;
+        let frame_ended = if self.mmu.ppu.lcdc() & 0x80 != 0 {
+            !was_vblank && self.mmu.ppu.mode() == 1
+        } else {
+            self.frame_ticks >= TICKS_PER_FRAME
+        };
+        if frame_ended {
+            self.frame_ticks = 0;
+        }
+        Ok((ticks, frame_ended))
+    }
+
+    pub fn ppu_updated(&mut self) -> bool {
+        let result = self.mmu.ppu.updated;
+        self.mmu.ppu.updated = false;
+        result
+    }
+}
+
+// Every section of a save state has a fixed size, so checking the length catches truncated or
+// padded states before anything is overwritten. A fresh snapshot is measured the first time.
+fn state_size() -> usize {
+    static SIZE: OnceLock<usize> = OnceLock::new();
+    *SIZE.get_or_init(|| Cpu::new().save_state().len())
+}
+
+impl<B: Bus> Cpu<B> {
+    // Creates a Cpu in the post-boot state over any memory bus.
+    pub fn with_bus(bus: B) -> Self {
+        Self {
             reg: Registers::new_default(),
             pc: 0x100,
             sp: 0xfffe,
             ime: false,
             tempIme: false,
-            mmu: MMU::new(),
+            mmu: bus,
             cycle: 0,
-            line: 0,
-            debug_file: Vec::new(),
+            tracer: None,
+            error: None,
+            frame_ticks: 0,
             halted: false,
             setdi: 0,
             setei: 0,
-        };
-
-        temp
+        }
     }
+
     pub fn reset(&mut self) {
         self.reg = Registers::new_default();
         self.pc = 0x100;
         self.sp = 0xfffe;
         self.ime = false;
         self.cycle = 0;
+        self.frame_ticks = 0;
     }
 
-    pub fn load(&mut self, data: &[u8]) {
-        self.mmu.load(data);
+    pub fn registers(&self) -> &Registers {
+        &self.reg
     }
-    
-    pub fn get_display(&self) -> &[u8] {
-        &self.mmu.ppu.screen_buffer.as_ref()
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn sp(&self) -> u16 {
+        self.sp
+    }
+
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.reg
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub fn set_sp(&mut self, sp: u16) {
+        self.sp = sp;
+    }
+
+    pub fn ime(&self) -> bool {
+        self.ime
+    }
+
+    pub fn set_ime(&mut self, ime: bool) {
+        self.ime = ime;
+    }
+
+    // Fetches and executes a single instruction, ignoring interrupts, HALT and the rest of the
+    // hardware. Returns the number of machine cycles it took.
+    pub fn step(&mut self) -> Result<u32, Error> {
+        let op = self.fetch_byte();
+        let cycles = self.execute(op);
+        self.take_error().map_or(Ok(cycles), Err)
+    }
+
+    // Starts logging every instruction, or stops when given None.
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
     }
 
-    pub fn do_cycle(&mut self) -> u32 {
+    pub fn tracer(&self) -> Option<&Tracer> {
+        self.tracer.as_ref()
+    }
+
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    // Runs one instruction, or services an interrupt, along with the rest of the hardware.
+    // Returns the number of clock ticks that took. Frontends should use run_frame or run_cycles
+    // instead, which also keep track of frames.
+    pub fn do_cycle(&mut self) -> Result<u32, Error> {
         let ticks = self.docycle() * 4;
-        return self.mmu.do_cycle(ticks);
+        // Nothing ran for an unimplemented opcode, so the rest of the hardware doesn't either.
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        // Bus errors come from an instruction that did run, so the hardware catches up with it first.
+        let ticks = self.mmu.tick(ticks);
+        self.mmu.take_error().map_or(Ok(ticks), Err)
+    }
+
+    fn take_error(&mut self) -> Option<Error> {
+        self.error.take().or_else(|| self.mmu.take_error())
     }
 
     fn docycle(&mut self) -> u32 {
@@ -72,28 +328,19 @@ impl Cpu {
         if self.halted {
             1
         } else {
+            if let Some(tracer) = self.tracer.as_mut() {
+                let pcmem = [0, 1, 2, 3].map(|i| self.mmu.peek(self.pc.wrapping_add(i)));
+                tracer.instruction(&self.reg, self.sp, self.pc, pcmem, self.mmu.bank_at(self.pc));
+            }
             let op = self.fetch_byte();
             self.execute(op)
         }
     } 
 
-    fn updateime(&mut self) {
-        self.setdi = match self.setdi {
-            2 => 1,
-            1 => { self.ime = false; 0 },
-            _ => 0,
-        };
-        self.setei = match self.setei {
-            2 => 1,
-            1 => { self.ime = true; 0 },
-            _ => 0,
-        };
-    }
-
     fn handleinterrupt(&mut self) -> u32 {
         if self.ime == false && self.halted == false { return 0 }
 
-        let triggered = self.mmu.inte & self.mmu.intf;
+        let triggered = self.mmu.pending_interrupts();
         if triggered == 0 { return 0 }
 
         self.halted = false;
@@ -101,8 +348,7 @@ impl Cpu {
         self.ime = false;
 
         let n = triggered.trailing_zeros();
-        if n >= 5 { panic!("Invalid interrupt triggered"); }
-        self.mmu.intf &= !(1 << n);
+        self.mmu.acknowledge_interrupt(n);
         let pc = self.pc;
         self.push(pc);
         self.pc = 0x0040 | ((n as u16) << 3);
@@ -110,6 +356,19 @@ impl Cpu {
         return 4
     }
 
+    fn updateime(&mut self) {
+        self.setdi = match self.setdi {
+            2 => 1,
+            1 => { self.ime = false; 0 },
+            _ => 0,
+        };
+        self.setei = match self.setei {
+            2 => 1,
+            1 => { self.ime = true; 0 },
+            _ => 0,
+        };
+    }
+
     fn fetch_byte(&mut self) -> u8 {
         let byte = self.mmu.read_byte(self.pc);
         self.pc += 1;
@@ -122,14 +381,8 @@ impl Cpu {
         word
     }
 
-    pub fn ppu_updated(&mut self) -> bool {
-        let result = self.mmu.ppu.updated;
-        self.mmu.ppu.updated = false;
-        result
-    }
-
     fn execute(&mut self, op: u8) -> u32{
-        let timing = match op {
+        match op {
             // Notation for LD functions:
             // LD(to_set, set_with)
             // 0x00 => {if self.depth > 100 {unimplemented!("Stop")} else {self.depth += 1;1}}
@@ -418,7 +671,7 @@ impl Cpu {
 
             0xcb => {
                 let op = self.fetch_byte();
-                let timing = match op { 
+                match op { 
                     0x00..=0x0f => {
                         let params = op;
                         if (params % 8) == 6 || (params % 8) == 0xe {
@@ -587,26 +840,19 @@ impl Cpu {
                             2
                         }
                     }
-                    _ => unimplemented!("Unimplemented CB prefixed opcode: {:#04x}", op)
-                };
-                timing
+                    _ => {
+                        self.pc = self.pc.wrapping_sub(2);
+                        self.error = Some(Error::UnimplementedOpcode { addr: self.pc, opcode: op, prefixed: true });
+                        0
+                    }
+                }
             }
-            _ => unimplemented!("Unimplemented opcode: {:#04x}", op),
-        };
-        if LOG_LEVEL >= 4 {
-            print!("length of execution {}\n", timing);
-        }
-        if LOG_LEVEL >= 3 {
-            let this = format!("{}", timing);
-            println!("{}", this); 
-            println!("{}", self.debug_file[(self.line)]);
-            if this != self.debug_file[self.line ]{
-                unimplemented!("Not matching original");
+            _ => {
+                self.pc = self.pc.wrapping_sub(1);
+                self.error = Some(Error::UnimplementedOpcode { addr: self.pc, opcode: op, prefixed: false });
+                0
             }
-            self.line += 1;
-
         }
-        timing
     }
 
     fn adc(&mut self, val: u8){
@@ -812,9 +1058,11 @@ impl Cpu {
         self.pc = pointer;
     }
 
+    // The high byte is written first, to the higher address, like on hardware.
     fn push(&mut self, val: u16) {
         self.sp = self.sp - 2;
-        self.mmu.write_word(self.sp, val);
+        self.mmu.write_byte(self.sp + 1, (val >> 8) as u8);
+        self.mmu.write_byte(self.sp, val as u8);
     }
 
     fn pop(&mut self) -> u16 { 
@@ -956,6 +1204,123 @@ mod test {
     //     assert_eq!(cpu.mmu.read_byte(cpu.reg.get_hl()), 0b1);
     // }
 
+    #[test]
+    fn save_state_round_trip() {
+        let mut cpu = Cpu::new();
+        cpu.reg.a = 0x42;
+        cpu.mmu.write_byte(0xC123, 0x99);
+        cpu.frame_ticks = 1000;
+        let state = cpu.save_state();
+
+        cpu.reg.a = 0;
+        cpu.pc = 0x1234;
+        cpu.mmu.write_byte(0xC123, 0);
+        cpu.frame_ticks = 0;
+        cpu.load_state(&state).unwrap();
+        assert_eq!(cpu.reg.a, 0x42);
+        assert_eq!(cpu.frame_ticks, 1000);
+        assert_eq!(cpu.pc, 0x100);
+        assert_eq!(cpu.mmu.read_byte(0xC123), 0x99);
+    }
+
+    #[test]
+    fn load_state_refuses_other_rom() {
+        let mut cpu = Cpu::new();
+        let state = cpu.save_state();
+        cpu.load(&[0x12; 0x150]).unwrap();
+        assert!(matches!(cpu.load_state(&state), Err(StateError::WrongRom { .. })));
+        let state = cpu.save_state();
+        assert_eq!(cpu.load_state(&state[..100]), Err(StateError::WrongSize));
+        assert_eq!(cpu.load_state(b"nope"), Err(StateError::BadMagic));
+    }
+
+    #[test]
+    fn load_state_refuses_impossible_timer_rates() {
+        let mut cpu = Cpu::new();
+        cpu.mmu.write_byte(0xFF07, 0b101);
+        let mut state = cpu.save_state();
+        // The rate of 16 ticks is the last u32 of 16 in the state, near the end with the timer.
+        let offset = state.windows(4).rposition(|w| w == 16u32.to_le_bytes()).unwrap();
+        state[offset..offset + 4].copy_from_slice(&0u32.to_le_bytes());
+        cpu.reg.a = 0x42;
+        cpu.mmu.write_byte(0xFF05, 0x34);
+        let before = cpu.save_state();
+        assert_eq!(cpu.load_state(&state), Err(StateError::Invalid));
+        assert_eq!(cpu.save_state(), before);
+    }
+
+    #[test]
+    fn illegal_opcodes_stop_at_the_instruction() {
+        let mut cpu = Cpu::new();
+        cpu.mmu.poke(0xC000, 0xD3);
+        cpu.pc = 0xC000;
+        let expected = Error::UnimplementedOpcode { addr: 0xC000, opcode: 0xD3, prefixed: false };
+        assert_eq!(cpu.step(), Err(expected));
+        assert_eq!(cpu.pc, 0xC000);
+        assert_eq!(cpu.do_cycle(), Err(expected));
+        assert_eq!(cpu.pc, 0xC000);
+
+        assert!(matches!(cpu.load(&vec![0; 0x100000]), Err(Error::RomTooLarge { size: 0x100000, .. })));
+    }
+
+    #[test]
+    fn bus_errors_still_tick_the_hardware() {
+        // LD ($4000),A, compared with the same write to WRAM.
+        let run = |addr: u16| {
+            let mut cpu = Cpu::new();
+            let [lo, hi] = addr.to_le_bytes();
+            for (i, byte) in [0xEA, lo, hi].into_iter().enumerate() {
+                cpu.mmu.poke(0xC000 + i as u16, byte);
+            }
+            cpu.pc = 0xC000;
+            let result = cpu.do_cycle();
+            (result, cpu.pc, cpu.mmu.ppu.mode(), cpu.mmu.read_byte(0xFF04))
+        };
+        let (result, pc, mode, div) = run(0x4000);
+        assert_eq!(result, Err(Error::UnsupportedBankSwitch { addr: 0x4000, value: 0x11 }));
+        let (ok, expected_pc, expected_mode, expected_div) = run(0xC100);
+        assert!(ok.is_ok());
+        assert_eq!((pc, mode, div), (expected_pc, expected_mode, expected_div));
+    }
+
+    #[test]
+    fn frames_end_without_the_lcd() {
+        let mut cpu = Cpu::new();
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x102].copy_from_slice(&[0x18, 0xFE]); // JR -2
+        cpu.load(&rom).unwrap();
+        // The first frame starts part way through, and every one after is as long as with the LCD off.
+        cpu.run_frame().unwrap();
+        let ticks = cpu.run_frame().unwrap();
+        assert!((TICKS_PER_FRAME - 24..TICKS_PER_FRAME + 24).contains(&ticks));
+        assert_eq!(cpu.mmu.ppu.mode(), 1);
+
+        cpu.mmu.write_byte(0xFF40, 0);
+        let ticks = cpu.run_frame().unwrap();
+        assert!((TICKS_PER_FRAME..TICKS_PER_FRAME + 24).contains(&ticks));
+        let ticks = cpu.run_cycles(1000).unwrap();
+        assert!((1000..1024).contains(&ticks));
+    }
+
+    #[test]
+    fn run_frame_stops_at_a_divergence() {
+        let mut cpu = Cpu::new();
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x104].copy_from_slice(&[0xF0, 0x44, 0x18, 0xFC]); // LDH A,(LY); JR -4
+        cpu.load(&rom).unwrap();
+        cpu.set_doctor_mode(true);
+        let reference = "A:11 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:F0,44,18,FC
+A:90 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0102 PCMEM:18,FC,00,00
+A:90 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:F0,44,18,FC
+A:00 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0102 PCMEM:18,FC,00,00
+";
+        cpu.set_tracer(Some(Tracer::new(None, Some(Box::new(std::io::Cursor::new(reference))))));
+        cpu.run_frame().unwrap();
+        let tracer = cpu.tracer().unwrap();
+        assert_eq!(tracer.divergence().unwrap().line, 4);
+        assert_eq!(cpu.pc, 0x100);
+    }
+
     #[test]
     fn xor_a() {
         let mut cpu = Cpu::new();
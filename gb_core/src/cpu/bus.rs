@@ -0,0 +1,145 @@
+use super::mmu::MMU;
+use crate::error::Error;
+
+// Everything the CPU is connected to: memory, the rest of the hardware and the interrupt lines.
+// The Cpu is generic over this so it can run against a flat 64 KiB array in tests, or against a
+// wrapper that traces or counts accesses before passing them on to the MMU.
+pub trait Bus {
+    fn read_byte(&self, loc: u16) -> u8;
+    fn write_byte(&mut self, loc: u16, data: u8);
+
+    // Reads for tools like the tracer, which mustn't trigger watchpoints or other side effects.
+    fn peek(&self, loc: u16) -> u8 {
+        self.read_byte(loc)
+    }
+
+    // The ROM or RAM bank mapped at `loc`, for matching addresses against symbols.
+    fn bank_at(&self, _loc: u16) -> u16 {
+        0
+    }
+
+    fn read_word(&self, loc: u16) -> u16 {
+        (self.read_byte(loc) as u16) | ((self.read_byte(loc.wrapping_add(1)) as u16) << 8)
+    }
+
+    fn write_word(&mut self, loc: u16, data: u16) {
+        self.write_byte(loc, data as u8);
+        self.write_byte(loc.wrapping_add(1), (data >> 8) as u8);
+    }
+
+    // Advances the rest of the hardware by the ticks the CPU just spent. Returns how many ticks
+    // actually passed.
+    fn tick(&mut self, ticks: u32) -> u32 {
+        ticks
+    }
+
+    // Interrupts that are both requested and enabled, as IF & IE bits.
+    fn pending_interrupts(&self) -> u8 {
+        0
+    }
+
+    // Clears the request for interrupt `n` once the CPU starts servicing it.
+    fn acknowledge_interrupt(&mut self, _n: u32) {}
+
+    // An error from an access during the last instruction, which the Cpu returns from step.
+    fn take_error(&mut self) -> Option<Error> {
+        None
+    }
+}
+
+impl Bus for MMU {
+    fn read_byte(&self, loc: u16) -> u8 {
+        MMU::read_byte(self, loc)
+    }
+
+    fn write_byte(&mut self, loc: u16, data: u8) {
+        MMU::write_byte(self, loc, data)
+    }
+
+    fn peek(&self, loc: u16) -> u8 {
+        MMU::peek(self, loc)
+    }
+
+    fn bank_at(&self, loc: u16) -> u16 {
+        MMU::bank_at(self, loc)
+    }
+
+    fn read_word(&self, loc: u16) -> u16 {
+        MMU::read_word(self, loc)
+    }
+
+    fn write_word(&mut self, loc: u16, data: u16) {
+        MMU::write_word(self, loc, data)
+    }
+
+    fn tick(&mut self, ticks: u32) -> u32 {
+        self.do_cycle(ticks)
+    }
+
+    // Only the low five bits are wired to interrupts.
+    fn pending_interrupts(&self) -> u8 {
+        self.inte & self.intf & 0x1F
+    }
+
+    fn acknowledge_interrupt(&mut self, n: u32) {
+        self.intf &= !(1 << n);
+    }
+
+    fn take_error(&mut self) -> Option<Error> {
+        self.error.take()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::Cpu;
+
+    // Counts writes on the way through to the MMU.
+    struct CountingBus {
+        inner: MMU,
+        writes: usize,
+    }
+
+    impl Bus for CountingBus {
+        fn read_byte(&self, loc: u16) -> u8 {
+            self.inner.read_byte(loc)
+        }
+
+        fn write_byte(&mut self, loc: u16, data: u8) {
+            self.writes += 1;
+            self.inner.write_byte(loc, data);
+        }
+
+        fn tick(&mut self, ticks: u32) -> u32 {
+            self.inner.tick(ticks)
+        }
+
+        fn pending_interrupts(&self) -> u8 {
+            self.inner.pending_interrupts()
+        }
+
+        fn acknowledge_interrupt(&mut self, n: u32) {
+            self.inner.acknowledge_interrupt(n);
+        }
+    }
+
+    #[test]
+    fn wrapper_sees_accesses_and_interrupts() {
+        let mut cpu = Cpu::with_bus(CountingBus { inner: MMU::new(), writes: 0 });
+        // EI; LD ($C000),A; NOP
+        for (i, op) in [0xFB, 0xEA, 0x00, 0xC0, 0x00].into_iter().enumerate() {
+            cpu.mmu.inner.write_byte(0xC100 + i as u16, op);
+        }
+        cpu.set_pc(0xC100);
+        cpu.mmu.inner.inte = 0x04;
+        cpu.do_cycle().unwrap();
+        cpu.do_cycle().unwrap();
+        assert_eq!(cpu.mmu.writes, 1);
+
+        cpu.mmu.inner.intf |= 0x04;
+        cpu.do_cycle().unwrap();
+        assert_eq!(cpu.pc(), 0x0050);
+        assert_eq!(cpu.mmu.inner.intf & 0x04, 0);
+    }
+}
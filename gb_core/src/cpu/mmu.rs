@@ -1,36 +1,65 @@
+use crate::debugger::{WatchHit, Watchpoint};
+use crate::error::Error;
 use crate::mbc;
 use crate::ppu::PPU;
 use crate::joypad::Joypad;
+use crate::serial::Serial;
+use crate::state::{self, StateError, StateReader, StateWriter};
 use crate::timer::Timer;
-use std::str;
+use std::cell::Cell;
 
 const ROM_SIZE: usize = 0x16000;
 const RAM_SIZE: usize = 0x5000;
 
-#[derive(PartialEq)]
-enum DMAType {
-    NoDMA,
-    GDMA,
-    HDMA,
-}
+// Bits of each I/O register from 0xFF00 that always read as 1, because they're unused or the
+// register doesn't exist on the DMG. 0xFF means nothing is mapped there.
+#[rustfmt::skip]
+const IO_READ_MASK: [u8; 0x80] = [
+    // P1    SB    SC          DIV   TIMA  TMA   TAC                                           IF
+    0xC0, 0x00, 0x7E, 0xFF, 0x00, 0x00, 0x00, 0xF8, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xE0,
+    // NR10 NR11  NR12  NR13  NR14        NR21  NR22  NR23  NR24  NR30  NR31  NR32  NR33  NR34
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, 0xFF, 0x3F, 0x00, 0xFF, 0xBF, 0x7F, 0xFF, 0x9F, 0xFF, 0xBF, 0xFF,
+    // NR41 NR42  NR43  NR44  NR50  NR51  NR52
+    0xFF, 0x00, 0x00, 0xBF, 0x00, 0x00, 0x70, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    // Wave RAM
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // LCDC STAT  SCY   SCX   LY    LYC   DMA   BGP   OBP0  OBP1  WY    WX
+    0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF,
+    //          HDMA1-5 are CGB only
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+];
+
 pub struct MMU {
     rom: [u8; ROM_SIZE],
     ram: [u8; RAM_SIZE],
     io: [u8; 0x80],
     hram: [u8; 0x7f],
-    hdma: [u8; 4],
     wram: [u8; ROM_SIZE],
     wram1: [u8; ROM_SIZE],
     pub ppu: PPU,
     pub joypad: Joypad,
-    hdma_status: DMAType,
-    hdma_src: u16,
-    hdma_dst: u16,
-    hdma_len: u8,
     pub timer: Timer,
+    pub serial: Serial,
     pub inte: u8,
     pub intf: u8,
     current_bank: u8,
+    // Installed by the debugger while it runs. The first matching access is recorded in
+    // watch_hit, which is a Cell because reads only borrow the MMU immutably.
+    pub(crate) watchpoints: Vec<Watchpoint>,
+    pub(crate) watch_hit: Cell<Option<WatchHit>>,
+    // Set by a write the emulator can't handle, for the Cpu to return once the instruction
+    // finishes.
+    pub(crate) error: Option<Error>,
+    // LY always reads this instead, as set by set_ly_stub.
+    ly_stub: Option<u8>,
+}
+
+impl Default for MMU {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MMU {
@@ -40,19 +69,19 @@ impl MMU {
             ram: [0; RAM_SIZE],
             io: [0; 0x80],
             hram: [0; 0x7f],
-            hdma: [0; 4],
             wram: [0xff; ROM_SIZE],
             wram1: [0xff; ROM_SIZE],
             ppu: PPU::new(),
             joypad: Joypad::new(),
-            hdma_src: 0,
-            hdma_dst: 0,
-            hdma_status: DMAType::NoDMA,
-            hdma_len: 0xFF,
             timer: Timer::new(),
+            serial: Serial::new(),
             inte: 0,
             intf: 0,
             current_bank: 1,
+            watchpoints: Vec::new(),
+            watch_hit: Cell::new(None),
+            error: None,
+            ly_stub: None,
         };
         mmu.set_initial();
         mmu
@@ -94,139 +123,112 @@ impl MMU {
 
     // Attempts to keep all components in sync. // TODO: Keep ppu in sync.
     pub fn do_cycle(&mut self, ticks: u32) -> u32 {
-        let cpudivider = 1;
-        let vramticks = self.perform_vramdma();
-        let gputicks = ticks / cpudivider + vramticks;
-        let cputicks = ticks + vramticks * cpudivider;
-
-        self.timer.do_cycle(cputicks);
+        self.timer.do_cycle(ticks);
         self.intf |= self.timer.interrupt;
         self.timer.interrupt = 0;
-        self.ppu.execute();
+        self.serial.do_cycle(ticks);
+        self.intf |= self.serial.interrupt;
+        self.serial.interrupt = 0;
+        self.intf |= self.joypad.interrupt;
+        self.joypad.interrupt = 0;
+        self.ppu.execute(ticks);
         self.intf |= self.ppu.interrupt;
         self.ppu.interrupt = 0;
-        return gputicks;
+        return ticks;
     }
 
     // Loads rom data into array of fixed length. This has not been tested for every MBC type.
-    pub fn load(&mut self, data: &[u8]) {
+    pub fn load(&mut self, data: &[u8]) -> Result<(), Error> {
+        if data.len() > ROM_SIZE {
+            return Err(Error::RomTooLarge { size: data.len(), max: ROM_SIZE });
+        }
         self.rom[0..data.len()].copy_from_slice(data);
+        Ok(())
     }
     
-    // Copies data from shadow OAM into original OAM.
-    fn oamdma(&mut self, value: u8) {
-        let base = (value as u16) << 8;
-        for i in 0 .. 0xA0 {
-            let b = self.read_byte(base + i);
-            self.write_byte(0xFE00 + i, b);
+    // The bank mapped at `addr`: the ROM bank for 0x4000-0x7FFF, 1 for the switchable WRAM bank,
+    // and 0 everywhere else.
+    pub fn bank_at(&self, addr: u16) -> u16 {
+        match addr {
+            0x4000..=0x7fff => self.current_bank as u16,
+            0xd000..=0xdfff => 1,
+            _ => 0,
         }
     }
 
-    pub fn perform_vramdma(&mut self) -> u32 {
-        match self.hdma_status {
-            DMAType::NoDMA => 0,
-            DMAType::GDMA => self.perform_gdma(),
-            DMAType::HDMA => self.perform_hdma(),
-        }
+    // Identifies the loaded game, so save states made for another one can be refused.
+    pub fn rom_checksum(&self) -> u32 {
+        state::crc32(&self.rom)
     }
 
-    fn perform_hdma(&mut self) -> u32 {
-        self.perform_vramdma_row();
-        if self.hdma_len == 0x7F { self.hdma_status = DMAType::NoDMA; }
-
-        return 8;
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.bytes(&self.ram);
+        w.bytes(&self.io);
+        w.bytes(&self.hram);
+        w.bytes(&self.wram);
+        w.bytes(&self.wram1);
+        w.u8(self.inte);
+        w.u8(self.intf);
+        w.u8(self.current_bank);
+        self.ppu.save_state(w);
+        self.joypad.save_state(w);
+        self.timer.save_state(w);
+        self.serial.save_state(w);
     }
 
-    fn perform_gdma(&mut self) -> u32 {
-        let len = self.hdma_len as u32 + 1;
-        for _i in 0 .. len {
-            self.perform_vramdma_row();
-        }
-
-        self.hdma_status = DMAType::NoDMA;
-        return len * 8;
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.bytes(&mut self.ram)?;
+        r.bytes(&mut self.io)?;
+        r.bytes(&mut self.hram)?;
+        r.bytes(&mut self.wram)?;
+        r.bytes(&mut self.wram1)?;
+        self.inte = r.u8()?;
+        self.intf = r.u8()?;
+        self.current_bank = r.u8()?;
+        self.ppu.load_state(r)?;
+        self.joypad.load_state(r)?;
+        self.timer.load_state(r)?;
+        self.serial.load_state(r)
     }
 
-    fn perform_vramdma_row(&mut self) {
-        let mmu_src = self.hdma_src;
-        for j in 0 .. 0x10 {
-            let b: u8 = self.read_byte(mmu_src + j);
-            self.ppu.write_byte(self.hdma_dst + j, b);
-        }
-        self.hdma_src += 0x10;
-        self.hdma_dst += 0x10;
-
-        if self.hdma_len == 0 {
-            self.hdma_len = 0x7F;
-        }
-        else {
-            self.hdma_len -= 1;
-        }
-    }
-
-    fn hdma_read(&self, a: u16) -> u8 {
-        match a {
-            0xFF51 ..= 0xFF54 => { self.hdma[(a - 0xFF51) as usize] },
-            0xFF55 => self.hdma_len | if self.hdma_status == DMAType::NoDMA { 0x80 } else { 0 },
-            _ => panic!("The address {:04X} should not be handled by hdma_read", a),
+    // Copies data from shadow OAM into original OAM.
+    fn oamdma(&mut self, value: u8) {
+        let base = (value as u16) << 8;
+        for i in 0 .. 0xA0 {
+            let b = self.read_byte(base + i);
+            self.write_byte(0xFE00 + i, b);
         }
     }
 
-    fn hdma_write(&mut self, a: u16, v: u8) {
-        match a {
-            0xFF51 => self.hdma[0] = v,
-            0xFF52 => self.hdma[1] = v & 0xF0,
-            0xFF53 => self.hdma[2] = v & 0x1F,
-            0xFF54 => self.hdma[3] = v & 0xF0,
-            0xFF55 => {
-                if self.hdma_status == DMAType::HDMA {
-                    if v & 0x80 == 0 { self.hdma_status = DMAType::NoDMA; };
-                    return;
-                }
-                let src = ((self.hdma[0] as u16) << 8) | (self.hdma[1] as u16);
-                let dst = ((self.hdma[2] as u16) << 8) | (self.hdma[3] as u16) | 0x8000;
-                if !(src <= 0x7FF0 || (src >= 0xA000 && src <= 0xDFF0)) { panic!("HDMA transfer with illegal start address {:04X}", src); }
-
-                self.hdma_src = src;
-                self.hdma_dst = dst;
-                self.hdma_len = v & 0x7F;
-
-                self.hdma_status =
-                    if v & 0x80 == 0x80 { DMAType::HDMA }
-                    else { DMAType::GDMA };
-            },
-            _ => panic!("The address {:04X} should not be handled by hdma_write", a),
-        };
-    }
     
     pub fn write_byte(&mut self, loc: u16, data: u8){
-        
-        if loc == 0xff01 {
-            let v = vec![self.read_byte(0xff01)];
-            print!("{} ", str::from_utf8(&v).unwrap());
-        }
-        if loc == 0xc7f6 {
-            let v = vec![self.read_byte(0xff01)];
-            print!("{} ", str::from_utf8(&v).unwrap());
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(loc, data, true);
         }
         match loc {
             0x0000..=0x1fff=> {}
             0x2000..=0x3fff=>{self.current_bank = (data & 0x0F);}
-            0x4000..=0x5fff=> {unimplemented!("RAM bank or additional rom bank switch, {} {} ", loc, data)}
+            0x4000..=0x5fff => self.error = Some(Error::UnsupportedBankSwitch { addr: loc, value: data }),
             0x8000..= 0x9FFF => self.ppu.write_byte(loc, data),
             0xA000..=0xbfff=> {self.ram[(loc - 0xA000) as usize] = data;}
             0xc000..=0xcfff=> {self.wram[(loc - 0xc000) as usize] = data;}
             0xd000..=0xdfff=> {self.wram1[(loc - 0xd000) as usize] = data;}
+            0xe000..=0xefff => self.wram[(loc - 0xe000) as usize] = data,
+            0xf000..=0xfdff => self.wram1[(loc - 0xf000) as usize] = data,
             0xfe00 ..= 0xfe9f => {self.ppu.write_byte(loc, data)},
             0xFF00 => {self.joypad.write(data)}
+            0xFF01 ..= 0xFF02 => self.serial.wb(loc, data),
             0xFF04 ..= 0xFF07 => self.timer.wb(loc, data),
             0xFF0F => self.intf = data,
             0xff00..=0xff3f => {self.io[(loc - 0xff00) as usize] = data}
-            0xff46 => self.oamdma(data),
-            0xFF4D => {}
-            0xFF40 ..= 0xFF4F => {self.ppu.write_byte(loc, data)},
-            0xFF51 ..= 0xFF55 => self.hdma_write(loc, data),
-            0xff68 ..= 0xff6b => self.ppu.write_byte(loc, data),
+            0xff46 => {
+                self.io[0x46] = data;
+                self.oamdma(data);
+            }
+            0xFF40 ..= 0xFF45 | 0xFF47 ..= 0xFF4B => {self.ppu.write_byte(loc, data)},
+            // CGB only, including the HDMA registers.
+            0xFF4C ..= 0xFF4F | 0xFF51 ..= 0xFF55 => {}
+                        0xff68 ..= 0xff6b => self.ppu.write_byte(loc, data),
             0xff6c..=0xff7f => {self.io[(loc - 0xff00) as usize] = data}
             0xff80..=0xfffe=> {self.hram[(loc as usize - 0xff80) as usize] = data}
             0xffff => {self.inte = data}
@@ -243,26 +245,108 @@ impl MMU {
     }
 
     pub fn read_byte(&self, loc: u16) -> u8 {
+        let data = self.peek(loc);
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(loc, data, false);
+        }
+        data
+    }
+
+    // Reads without triggering watchpoints, for debugging tools. No read has other side effects,
+    // and unmapped addresses read as 0xFF.
+    pub fn peek(&self, loc: u16) -> u8 {
         match loc {
             0x0000..=0x3fff=> {self.rom[loc as usize]}
-            0x4000..=0x7fff=> {let offset = loc - 0x4000; self.rom[(((self.current_bank as u16) * 0x4000) + offset) as usize]} // Offsets read location using rom bank number
+            0x4000..=0x7fff=> self.peek_banked(self.current_bank as u16, loc),
             0x8000 ..= 0x9FFF => self.ppu.read_byte(loc),
             0xA000..=0xbfff=> {self.ram[(loc - 0xA000) as usize]}
             0xc000..=0xcfff=> {self.wram[(loc - 0xc000) as usize]}
             0xd000..=0xdfff=> {self.wram1[(loc - 0xd000) as usize]}
+            // Echo RAM mirrors 0xC000-0xDDFF.
+            0xe000..=0xefff => self.wram[(loc - 0xe000) as usize],
+            0xf000..=0xfdff => self.wram1[(loc - 0xf000) as usize],
             0xfe00 ..= 0xfe9f => {self.ppu.read_byte(loc)},
             0xfea0..=0xfeff=> {0xFF}
-            0xFF00 => {self.joypad.read()}
-            0xFF04 ..= 0xFF07 => self.timer.rb(loc),
-            0xFF0F => self.intf,
-            0xff00..=0xff3f => {self.io[(loc - 0xff00) as usize]}
-            0xFF40 ..= 0xFF4F => self.ppu.read_byte(loc),
-            0xFF51 ..= 0xFF55 => self.hdma_read(loc),
-            0xff68 ..= 0xff6b => self.ppu.read_byte(loc),
-            0xff6c..=0xff7f => {self.io[(loc - 0xff00) as usize]}
+            0xff00..=0xff7f => self.peek_io(loc),
             0xff80..=0xfffe=> {self.hram[(loc as usize - 0xff80) as usize]}
             0xffff => {self.inte}
-            _ => unimplemented!("Undefined read location {:#04x}", loc)
+        }
+    }
+
+    // Makes LY read as `ly` no matter where the PPU is, or as normal again with None. Gameboy
+    // Doctor reference logs are made with LY stuck at 0x90, so tracing against them needs this.
+    pub fn set_ly_stub(&mut self, ly: Option<u8>) {
+        self.ly_stub = ly;
+    }
+
+    fn peek_io(&self, loc: u16) -> u8 {
+        let mask = IO_READ_MASK[(loc - 0xff00) as usize];
+        if mask == 0xFF {
+            return 0xFF;
+        }
+        if let (0xFF44, Some(ly)) = (loc, self.ly_stub) {
+            return ly;
+        }
+        let value = match loc {
+            0xFF00 => self.joypad.read(),
+            0xFF01 ..= 0xFF02 => self.serial.rb(loc),
+            0xFF04 ..= 0xFF07 => self.timer.rb(loc),
+            0xFF0F => self.intf,
+            0xFF40 ..= 0xFF45 | 0xFF47 ..= 0xFF4B => self.ppu.read_byte(loc),
+            _ => self.io[(loc - 0xff00) as usize],
+        };
+        value | mask
+    }
+
+    // Like peek, but reads the switchable ROM, cartridge RAM and WRAM regions from `bank`
+    // whatever is currently mapped there. Banks that don't exist read as 0xFF.
+    pub fn peek_banked(&self, bank: u16, loc: u16) -> u8 {
+        let bank = bank as usize;
+        let byte = match loc {
+            0x4000..=0x7fff => self.rom.get(bank * 0x4000 + (loc - 0x4000) as usize),
+            0xa000..=0xbfff => self.ram.get(bank * 0x2000 + (loc - 0xA000) as usize),
+            // There's only one WRAM bank without CGB support, and bank 0 selects bank 1.
+            0xd000..=0xdfff if bank <= 1 => Some(&self.wram1[(loc - 0xd000) as usize]),
+            0xd000..=0xdfff => None,
+            _ => return self.peek(loc),
+        };
+        byte.copied().unwrap_or(0xFF)
+    }
+
+    // Writes for debugging tools. Unlike write_byte this doesn't trigger watchpoints or the side
+    // effects of writing I/O registers, like resetting DIV or starting a DMA transfer, and writes
+    // to ROM patch the ROM in the mapped bank rather than switching banks. Registers that can't be
+    // written without side effects are left alone.
+    pub fn poke(&mut self, loc: u16, data: u8) {
+        match loc {
+            0x0000..=0x3fff => self.rom[loc as usize] = data,
+            0x4000..=0x7fff => {
+                let offset = self.current_bank as usize * 0x4000 + (loc - 0x4000) as usize;
+                if let Some(byte) = self.rom.get_mut(offset) {
+                    *byte = data;
+                }
+            }
+            0x8000..=0x9fff | 0xfe00..=0xfe9f => self.ppu.write_byte(loc, data),
+            0xa000..=0xbfff => self.ram[(loc - 0xA000) as usize] = data,
+            0xc000..=0xcfff => self.wram[(loc - 0xc000) as usize] = data,
+            0xd000..=0xdfff => self.wram1[(loc - 0xd000) as usize] = data,
+            0xe000..=0xefff => self.wram[(loc - 0xe000) as usize] = data,
+            0xf000..=0xfdff => self.wram1[(loc - 0xf000) as usize] = data,
+            0xFF01 => self.serial.wb(loc, data),
+            0xFF05..=0xFF07 => self.timer.wb(loc, data),
+            0xFF0F => self.intf = data,
+            0xff08..=0xff3f => self.io[(loc - 0xff00) as usize] = data,
+            0xFF40..=0xFF43 | 0xFF45 | 0xFF47..=0xFF4B => self.ppu.write_byte(loc, data),
+            0xff6c..=0xff7f => self.io[(loc - 0xff00) as usize] = data,
+            0xff80..=0xfffe => self.hram[(loc - 0xff80) as usize] = data,
+            0xffff => self.inte = data,
+            _ => {}
+        }
+    }
+
+    fn check_watchpoints(&self, addr: u16, value: u8, write: bool) {
+        if self.watch_hit.get().is_none() && self.watchpoints.iter().any(|w| w.matches(addr, value, write)) {
+            self.watch_hit.set(Some(WatchHit { addr, value, write }));
         }
     }
 
@@ -271,4 +355,104 @@ impl MMU {
     }
 
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn peek_never_panics() {
+        let mmu = MMU::new();
+        assert_eq!(mmu.peek(0xFEA0), 0xFF);
+        assert_eq!(mmu.peek_banked(0x40, 0x4000), 0xFF);
+        assert_eq!(mmu.peek_banked(3, 0xA000), 0xFF);
+        assert_eq!(mmu.peek_banked(2, 0xD000), 0xFF);
+    }
+
+    #[test]
+    fn peek_banked_ignores_the_mapping() {
+        let mut rom = vec![0; 0x10000];
+        rom[0x4000] = 1;
+        rom[0x8000] = 2;
+        rom[0xC001] = 3;
+        let mut mmu = MMU::new();
+        mmu.load(&rom).unwrap();
+        assert_eq!((mmu.peek_banked(1, 0x4000), mmu.peek_banked(2, 0x4000), mmu.peek_banked(3, 0x4001)), (1, 2, 3));
+        assert_eq!(mmu.peek(0x4000), 1);
+        assert_eq!(mmu.peek_banked(0, 0x0000), 0);
+        mmu.poke(0xD010, 0x42);
+        assert_eq!(mmu.peek_banked(1, 0xD010), 0x42);
+    }
+
+    #[test]
+    fn echo_ram_mirrors_wram() {
+        let mut mmu = MMU::new();
+        mmu.write_byte(0xC123, 0x12);
+        mmu.write_byte(0xFDFF, 0x34);
+        assert_eq!(mmu.read_byte(0xE123), 0x12);
+        assert_eq!(mmu.read_byte(0xDDFF), 0x34);
+        mmu.poke(0xF000, 0x56);
+        assert_eq!(mmu.peek(0xD000), 0x56);
+    }
+
+    #[test]
+    fn unused_io_bits_read_as_one() {
+        let mut mmu = MMU::new();
+        mmu.write_byte(0xFF0F, 0x01);
+        mmu.write_byte(0xFF07, 0x05);
+        mmu.write_byte(0xFF46, 0xC0);
+        mmu.write_byte(0xFF4C, 0x12);
+        assert_eq!(mmu.read_byte(0xFF0F), 0xE1);
+        assert_eq!(mmu.read_byte(0xFF07), 0xFD);
+        assert_eq!(mmu.read_byte(0xFF46), 0xC0);
+        assert_eq!(mmu.read_byte(0xFF03), 0xFF);
+        assert_eq!(mmu.read_byte(0xFF4C), 0xFF);
+        assert_eq!(mmu.read_byte(0xFF68), 0xFF);
+        assert_eq!(mmu.read_byte(0xFF55), 0xFF);
+        assert_eq!(mmu.read_byte(0xFF26) & 0x70, 0x70);
+        assert_eq!(mmu.read_byte(0xFF30), 0x00);
+    }
+
+    #[test]
+    fn hdma_writes_are_ignored() {
+        let mut mmu = MMU::new();
+        mmu.write_byte(0xC000, 0x12);
+        mmu.write_byte(0xFF51, 0xC0);
+        mmu.write_byte(0xFF52, 0x00);
+        mmu.write_byte(0xFF53, 0x00);
+        mmu.write_byte(0xFF54, 0x00);
+        mmu.write_byte(0xFF55, 0x00);
+        assert_eq!(mmu.do_cycle(4), 4);
+        assert_eq!(mmu.read_byte(0x8000), 0x00);
+        assert_eq!(mmu.read_byte(0xFF55), 0xFF);
+    }
+
+    #[test]
+    fn poke_has_no_side_effects() {
+        let mut mmu = MMU::new();
+        mmu.write_byte(0xFF05, 0x12);
+        mmu.poke(0xFF04, 0x55);
+        mmu.poke(0xFF46, 0xC0);
+        assert_eq!(mmu.peek(0xFF05), 0x12);
+        assert_eq!(mmu.peek(0xFE00), 0);
+        mmu.poke(0xFF05, 0x34);
+        assert_eq!(mmu.peek(0xFF05), 0x34);
+
+        // Writes to ROM patch it instead of switching banks.
+        mmu.poke(0x2000, 0x03);
+        mmu.poke(0x4001, 0x99);
+        assert_eq!(mmu.bank_at(0x4000), 1);
+        assert_eq!(mmu.peek(0x2000), 0x03);
+        assert_eq!(mmu.peek_banked(1, 0x4001), 0x99);
+    }
+
+    #[test]
+    fn ly_stub() {
+        let mut mmu = MMU::new();
+        mmu.set_ly_stub(Some(0x90));
+        assert_eq!(mmu.read_byte(0xFF44), 0x90);
+        mmu.set_ly_stub(None);
+        assert_eq!(mmu.read_byte(0xFF44), mmu.ppu.read_byte(0xFF44));
+    }
+}
@@ -1,3 +1,5 @@
+use crate::state::{StateError, StateReader, StateWriter};
+
 pub struct Registers {
     pub a: u8,
     pub b: u8,
@@ -23,6 +25,17 @@ impl Registers{
     pub fn new_default() -> Registers{
         Registers { a: 0x11, b: 0x00, c: 0x13, d: 0x00, e: 0xd8, f: 0xb0, h: 0x01, l: 0x4d}
     }
+    pub fn save_state(&self, w: &mut StateWriter) {
+        for v in [self.a, self.b, self.c, self.d, self.e, self.f, self.h, self.l] {
+            w.u8(v);
+        }
+    }
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        for v in [&mut self.a, &mut self.b, &mut self.c, &mut self.d, &mut self.e, &mut self.f, &mut self.h, &mut self.l] {
+            *v = r.u8()?;
+        }
+        Ok(())
+    }
     pub fn get_af(&self) -> u16{
         (self.a as u16) << 8 | self.f as u16
     }
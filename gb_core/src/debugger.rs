@@ -0,0 +1,376 @@
+use crate::cpu::Cpu;
+use crate::disasm;
+use crate::error::Error;
+use std::mem;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Any,
+}
+
+// Stops execution when `addr` is accessed, optionally only when the value read or written is
+// `value`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub addr: u16,
+    pub access: Access,
+    pub value: Option<u8>,
+}
+
+impl Watchpoint {
+    pub(crate) fn matches(&self, addr: u16, value: u8, write: bool) -> bool {
+        let access = match self.access {
+            Access::Read => !write,
+            Access::Write => write,
+            Access::Any => true,
+        };
+        self.addr == addr && access && self.value.is_none_or(|v| v == value)
+    }
+}
+
+// Stops execution before the instruction at `addr`. With a bank, only while that bank is mapped
+// there, so a label in one ROM bank doesn't stop at the same offset in the others.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub addr: u16,
+    pub bank: Option<u16>,
+}
+
+impl Breakpoint {
+    // A breakpoint in whatever bank is mapped at `addr`.
+    pub fn at(addr: u16) -> Self {
+        Self { addr, bank: None }
+    }
+
+    pub fn banked(addr: u16, bank: u16) -> Self {
+        Self { addr, bank: Some(bank) }
+    }
+
+    fn matches(&self, cpu: &Cpu, pc: u16) -> bool {
+        self.addr == pc && self.bank.is_none_or(|bank| cpu.mmu.bank_at(pc) == bank)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WatchHit {
+    pub addr: u16,
+    pub value: u8,
+    pub write: bool,
+}
+
+// Why execution stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stop {
+    // About to execute the instruction at this address.
+    Breakpoint(u16),
+    // The instruction that was just executed made this access.
+    Watchpoint(WatchHit),
+    // A step requested with step_into, step_over or step_out has finished.
+    Step,
+    // The frame requested with run_to_frame has been reached.
+    Frame(u64),
+    // The Cpu's tracer stopped matching its reference log, see Tracer::divergence.
+    Divergence,
+    // The game did something the emulator can't handle. Execution can't usefully go on, though
+    // the state can still be inspected.
+    Error(Error),
+}
+
+// What the debugger is running towards, besides breakpoints and watchpoints.
+#[derive(Clone, Copy)]
+enum Target {
+    Step,
+    Address { pc: u16, sp: u16 },
+    Return { sp: u16 },
+    Frame(u64),
+}
+
+// Runs a Cpu while watching for breakpoints, watchpoints and stepping targets.
+//
+// All execution goes through run_frame, which runs at most one frame so frontends can keep drawing
+// and handling input. The step and run_to_frame methods only set a target, so call run_frame
+// until it returns a Stop to carry them out.
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    target: Option<Target>,
+    // Set after stopping at a breakpoint, so resuming executes that instruction instead of
+    // stopping at it again.
+    resume_at: Option<u16>,
+    frame: u64,
+    // Line of the last divergence reported, so each one only stops execution once.
+    divergence: Option<u64>,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self {
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            target: None,
+            resume_at: None,
+            frame: 0,
+            divergence: None,
+        }
+    }
+
+    // Returns false if there already was the same breakpoint.
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> bool {
+        if self.breakpoints.contains(&breakpoint) {
+            return false;
+        }
+        self.breakpoints.push(breakpoint);
+        true
+    }
+
+    pub fn remove_breakpoint(&mut self, breakpoint: Breakpoint) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|&b| b != breakpoint);
+        self.breakpoints.len() != len
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    pub fn remove_watchpoint(&mut self, index: usize) -> Option<Watchpoint> {
+        (index < self.watchpoints.len()).then(|| self.watchpoints.remove(index))
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    // Number of frames run so far.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    // Stops after the next instruction.
+    pub fn step_into(&mut self) {
+        self.target = Some(Target::Step);
+    }
+
+    // Like step_into, but runs a CALL or RST until it returns.
+    pub fn step_over(&mut self, cpu: &Cpu) {
+        let instruction = disasm::decode_live(cpu, cpu.pc());
+        if !matches!(instruction.mnemonic, "CALL" | "RST") {
+            return self.step_into();
+        }
+        let pc = instruction.addr.wrapping_add(instruction.length as u16);
+        self.target = Some(Target::Address { pc, sp: cpu.sp() });
+    }
+
+    // Runs until the current function returns.
+    pub fn step_out(&mut self, cpu: &Cpu) {
+        self.target = Some(Target::Return { sp: cpu.sp() });
+    }
+
+    // Runs until `frame` frames have been run in total.
+    pub fn run_to_frame(&mut self, frame: u64) {
+        self.target = Some(Target::Frame(frame));
+    }
+
+    // Forgets any step or frame target, so run_frame only stops at breakpoints and watchpoints.
+    pub fn cancel(&mut self) {
+        self.target = None;
+    }
+
+    // Runs until the end of the frame, as Cpu::run_frame does, or until something makes execution stop.
+    pub fn run_frame(&mut self, cpu: &mut Cpu) -> Option<Stop> {
+        // The MMU checks watchpoints on every access, so they only need to be there while running.
+        mem::swap(&mut self.watchpoints, &mut cpu.mmu.watchpoints);
+        let stop = self.run(cpu);
+        mem::swap(&mut self.watchpoints, &mut cpu.mmu.watchpoints);
+        if stop.is_some() {
+            self.target = None;
+        }
+        stop
+    }
+
+    fn run(&mut self, cpu: &mut Cpu) -> Option<Stop> {
+        loop {
+            let pc = cpu.pc();
+            if self.resume_at != Some(pc) && self.breakpoints.iter().any(|b| b.matches(cpu, pc)) {
+                self.resume_at = Some(pc);
+                return Some(Stop::Breakpoint(pc));
+            }
+            self.resume_at = None;
+
+            let op = cpu.mmu.peek(pc);
+            let frame_ended = match cpu.frame_cycle() {
+                Ok((_, frame_ended)) => frame_ended,
+                Err(e) => return Some(Stop::Error(e)),
+            };
+            if let Some(hit) = cpu.mmu.watch_hit.take() {
+                return Some(Stop::Watchpoint(hit));
+            }
+            let divergence = cpu.tracer().and_then(|t| t.divergence()).map(|d| d.line);
+            if divergence.is_some() && divergence != self.divergence {
+                self.divergence = divergence;
+                return Some(Stop::Divergence);
+            }
+
+            let done = match self.target {
+                Some(Target::Step) => true,
+                Some(Target::Address { pc, sp }) => cpu.pc() == pc && cpu.sp() >= sp,
+                Some(Target::Return { sp }) => {
+                    matches!(op, 0xC0 | 0xC8 | 0xC9 | 0xD0 | 0xD8 | 0xD9) && cpu.sp() > sp
+                }
+                Some(Target::Frame(_)) | None => false,
+            };
+            if done {
+                return Some(Stop::Step);
+            }
+
+            if frame_ended {
+                self.frame += 1;
+                return match self.target {
+                    Some(Target::Frame(frame)) if self.frame >= frame => Some(Stop::Frame(self.frame)),
+                    _ => None,
+                };
+            }
+        }
+    }
+}
+
+// Reads a register by name: a, f, b, c, d, e, h, l, af, bc, de, hl, sp or pc.
+pub fn register(cpu: &Cpu, name: &str) -> Option<u16> {
+    let r = cpu.registers();
+    Some(match name.to_ascii_lowercase().as_str() {
+        "a" => r.a as u16,
+        "f" => r.f as u16,
+        "b" => r.b as u16,
+        "c" => r.c as u16,
+        "d" => r.d as u16,
+        "e" => r.e as u16,
+        "h" => r.h as u16,
+        "l" => r.l as u16,
+        "af" => r.get_af(),
+        "bc" => r.get_bc(),
+        "de" => r.get_de(),
+        "hl" => r.get_hl(),
+        "sp" => cpu.sp(),
+        "pc" => cpu.pc(),
+        _ => return None,
+    })
+}
+
+// Sets a register by name, truncating the value for 8 bit registers. Returns false for unknown
+// names.
+pub fn set_register(cpu: &mut Cpu, name: &str, value: u16) -> bool {
+    let r = cpu.registers_mut();
+    match name.to_ascii_lowercase().as_str() {
+        "a" => r.a = value as u8,
+        "f" => r.f = value as u8 & 0xF0,
+        "b" => r.b = value as u8,
+        "c" => r.c = value as u8,
+        "d" => r.d = value as u8,
+        "e" => r.e = value as u8,
+        "h" => r.h = value as u8,
+        "l" => r.l = value as u8,
+        "af" => r.set_af(value & 0xFFF0),
+        "bc" => r.set_bc(value),
+        "de" => r.set_de(value),
+        "hl" => r.set_hl(value),
+        "sp" => cpu.set_sp(value),
+        "pc" => cpu.set_pc(value),
+        _ => return false,
+    }
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::test_cpu;
+    use crate::trace::Tracer;
+
+    fn run(debugger: &mut Debugger, cpu: &mut Cpu) -> Stop {
+        (0..10).find_map(|_| debugger.run_frame(cpu)).expect("Debugger didn't stop")
+    }
+
+    #[test]
+    fn breakpoints_and_steps() {
+        let mut cpu = test_cpu();
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(Breakpoint::at(0x0150));
+        assert_eq!(run(&mut debugger, &mut cpu), Stop::Breakpoint(0x0150));
+
+        debugger.step_into();
+        assert_eq!(run(&mut debugger, &mut cpu), Stop::Step);
+        assert_eq!(cpu.pc(), 0x0151);
+        debugger.step_out(&cpu);
+        assert_eq!(run(&mut debugger, &mut cpu), Stop::Step);
+        assert_eq!(cpu.pc(), 0x0103);
+
+        cpu.set_pc(0x0100);
+        debugger.remove_breakpoint(Breakpoint::at(0x0150));
+        debugger.step_over(&cpu);
+        assert_eq!(run(&mut debugger, &mut cpu), Stop::Step);
+        assert_eq!(cpu.pc(), 0x0103);
+    }
+
+    #[test]
+    fn banked_breakpoints() {
+        let mut cpu = test_cpu();
+        let mut debugger = Debugger::new();
+        // $0150 is in bank 0, so a breakpoint for it in bank 2 never stops.
+        debugger.add_breakpoint(Breakpoint::banked(0x0150, 2));
+        debugger.run_to_frame(1);
+        assert_eq!(run(&mut debugger, &mut cpu), Stop::Frame(1));
+        debugger.add_breakpoint(Breakpoint::banked(0x0106, 0));
+        assert_eq!(run(&mut debugger, &mut cpu), Stop::Breakpoint(0x0106));
+    }
+
+    #[test]
+    fn watchpoint_with_value() {
+        let mut cpu = test_cpu();
+        let mut debugger = Debugger::new();
+        // A starts out as $11, so the first pass through the program stores $12.
+        debugger.add_watchpoint(Watchpoint { addr: 0xC000, access: Access::Write, value: Some(0x13) });
+        debugger.run_to_frame(2);
+        assert_eq!(run(&mut debugger, &mut cpu), Stop::Frame(2));
+
+        cpu.set_pc(0x0100);
+        set_register(&mut cpu, "a", 0x12);
+        assert_eq!(
+            run(&mut debugger, &mut cpu),
+            Stop::Watchpoint(WatchHit { addr: 0xC000, value: 0x13, write: true })
+        );
+        assert_eq!(register(&cpu, "pc"), Some(0x0106));
+    }
+
+    #[test]
+    fn stops_on_trace_divergence() {
+        let mut cpu = test_cpu();
+        let reference = "A:11 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:CD,50,01,EA\n";
+        let reference: Box<dyn std::io::BufRead> = Box::new(std::io::Cursor::new(reference));
+        cpu.set_tracer(Some(Tracer::new(None, Some(reference))));
+        let mut debugger = Debugger::new();
+        assert_eq!(run(&mut debugger, &mut cpu), Stop::Divergence);
+        assert_eq!(cpu.tracer().unwrap().divergence().unwrap().line, 2);
+        assert_eq!(debugger.run_frame(&mut cpu), None);
+    }
+
+    #[test]
+    fn runs_to_frame() {
+        let mut cpu = test_cpu();
+        let mut debugger = Debugger::new();
+        debugger.run_to_frame(3);
+        assert_eq!(run(&mut debugger, &mut cpu), Stop::Frame(3));
+        assert_eq!(debugger.frame(), 3);
+    }
+}
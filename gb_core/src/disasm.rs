@@ -0,0 +1,342 @@
+use crate::cpu::Cpu;
+use std::fmt;
+
+const R: [&str; 8] = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+const RP: [&str; 4] = ["BC", "DE", "HL", "SP"];
+const RP2: [&str; 4] = ["BC", "DE", "HL", "AF"];
+const CC: [&str; 4] = ["NZ", "Z", "NC", "C"];
+const ALU: [(&str, &str); 8] = [
+    ("ADD", "A,"),
+    ("ADC", "A,"),
+    ("SUB", ""),
+    ("SBC", "A,"),
+    ("AND", ""),
+    ("XOR", ""),
+    ("OR", ""),
+    ("CP", ""),
+];
+const ROT: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+const ACC: [&str; 8] = ["RLCA", "RRCA", "RLA", "RRA", "DAA", "CPL", "SCF", "CCF"];
+
+// A decoded instruction. Cycle counts are in machine cycles, like the values Cpu::step returns.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Instruction {
+    pub addr: u16,
+    pub length: u8,
+    pub mnemonic: &'static str,
+    pub operands: String,
+    // Cycles taken, or for conditional jumps, calls and returns the cycles when not taken.
+    pub cycles: u8,
+    // Cycles taken by a conditional jump, call or return when the condition holds.
+    pub cycles_taken: Option<u8>,
+    // Destination of a JP, JR, CALL or RST with a fixed address.
+    pub target: Option<u16>,
+    // Name of the I/O register the instruction accesses, if any.
+    pub io_register: Option<&'static str>,
+}
+
+impl Instruction {
+    // Formats the instruction, with its target replaced by a label when `label` returns one.
+    pub fn text_with(&self, label: impl Fn(u16) -> Option<String>) -> String {
+        let mut operands = self.operands.clone();
+        if let Some(name) = self.target.and_then(label) {
+            operands = operands.replace(&format!("${:04X}", self.target.unwrap()), &name);
+        }
+        let mut text = String::from(self.mnemonic);
+        if !operands.is_empty() {
+            text = format!("{} {}", text, operands);
+        }
+        if let Some(name) = self.io_register {
+            text = format!("{:20} ; {}", text, name);
+        }
+        text
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.text_with(|_| None))
+    }
+}
+
+// Decodes the instruction at `addr`, reading memory through `read`.
+pub fn decode(addr: u16, read: impl Fn(u16) -> u8) -> Instruction {
+    let op = read(addr);
+    let d8 = read(addr.wrapping_add(1));
+    let d16 = d8 as u16 | (read(addr.wrapping_add(2)) as u16) << 8;
+    let relative = addr.wrapping_add(2).wrapping_add(d8 as i8 as u16);
+    let mut i = Instruction {
+        addr,
+        length: 1,
+        mnemonic: "",
+        operands: String::new(),
+        cycles: 1,
+        cycles_taken: None,
+        target: None,
+        io_register: None,
+    };
+    let mut set = |mnemonic, operands: String, length, cycles| {
+        i.mnemonic = mnemonic;
+        i.operands = operands;
+        i.length = length;
+        i.cycles = cycles;
+    };
+
+    let (x, y, z) = (op >> 6, (op >> 3) & 7, op & 7);
+    let (p, q) = (y as usize >> 1, y & 1);
+    // Accessing (HL) costs one more cycle than a register, two for read-modify-write.
+    let hl = |r: u8, extra: u8| if r == 6 { extra } else { 0 };
+    match (x, z) {
+        (0, 0) => match y {
+            0 => set("NOP", String::new(), 1, 1),
+            1 => set("LD", format!("(${:04X}),SP", d16), 3, 5),
+            2 => set("STOP", String::new(), 2, 1),
+            3 => set("JR", format!("${:04X}", relative), 2, 3),
+            _ => set("JR", format!("{},${:04X}", CC[y as usize - 4], relative), 2, 2),
+        },
+        (0, 1) if q == 0 => set("LD", format!("{},${:04X}", RP[p], d16), 3, 3),
+        (0, 1) => set("ADD", format!("HL,{}", RP[p]), 1, 2),
+        (0, 2) => {
+            let mem = ["(BC)", "(DE)", "(HL+)", "(HL-)"][p];
+            let operands = if q == 0 { format!("{},A", mem) } else { format!("A,{}", mem) };
+            set("LD", operands, 1, 2)
+        }
+        (0, 3) => set(if q == 0 { "INC" } else { "DEC" }, String::from(RP[p]), 1, 2),
+        (0, 4) => set("INC", String::from(R[y as usize]), 1, 1 + hl(y, 2)),
+        (0, 5) => set("DEC", String::from(R[y as usize]), 1, 1 + hl(y, 2)),
+        (0, 6) => set("LD", format!("{},${:02X}", R[y as usize], d8), 2, 2 + hl(y, 1)),
+        (0, 7) => set(ACC[y as usize], String::new(), 1, 1),
+        (1, 6) if y == 6 => set("HALT", String::new(), 1, 1),
+        (1, _) => set("LD", format!("{},{}", R[y as usize], R[z as usize]), 1, 1 + hl(y, 1) + hl(z, 1)),
+        (2, _) => {
+            let (mnemonic, prefix) = ALU[y as usize];
+            set(mnemonic, format!("{}{}", prefix, R[z as usize]), 1, 1 + hl(z, 1))
+        }
+        (3, 0) => match y {
+            0..=3 => set("RET", String::from(CC[y as usize]), 1, 2),
+            4 => set("LDH", format!("(${:02X}),A", d8), 2, 3),
+            5 => set("ADD", format!("SP,{}", d8 as i8), 2, 4),
+            6 => set("LDH", format!("A,(${:02X})", d8), 2, 3),
+            _ => set("LD", format!("HL,SP{:+}", d8 as i8), 2, 3),
+        },
+        (3, 1) if q == 0 => set("POP", String::from(RP2[p]), 1, 3),
+        (3, 1) => match p {
+            0 => set("RET", String::new(), 1, 4),
+            1 => set("RETI", String::new(), 1, 4),
+            2 => set("JP", String::from("HL"), 1, 1),
+            _ => set("LD", String::from("SP,HL"), 1, 2),
+        },
+        (3, 2) => match y {
+            0..=3 => set("JP", format!("{},${:04X}", CC[y as usize], d16), 3, 3),
+            4 => set("LD", String::from("($FF00+C),A"), 1, 2),
+            5 => set("LD", format!("(${:04X}),A", d16), 3, 4),
+            6 => set("LD", String::from("A,($FF00+C)"), 1, 2),
+            _ => set("LD", format!("A,(${:04X})", d16), 3, 4),
+        },
+        (3, 3) => match y {
+            0 => set("JP", format!("${:04X}", d16), 3, 4),
+            1 => return decode_cb(addr, d8),
+            6 => set("DI", String::new(), 1, 1),
+            7 => set("EI", String::new(), 1, 1),
+            _ => set("DB", format!("${:02X}", op), 1, 1),
+        },
+        (3, 4) if y < 4 => set("CALL", format!("{},${:04X}", CC[y as usize], d16), 3, 3),
+        (3, 5) if q == 0 => set("PUSH", String::from(RP2[p]), 1, 4),
+        (3, 5) if p == 0 => set("CALL", format!("${:04X}", d16), 3, 6),
+        (3, 6) => {
+            let (mnemonic, prefix) = ALU[y as usize];
+            set(mnemonic, format!("{}${:02X}", prefix, d8), 2, 2)
+        }
+        (3, 7) => set("RST", format!("${:02X}", y * 8), 1, 4),
+        _ => set("DB", format!("${:02X}", op), 1, 1),
+    }
+
+    i.cycles_taken = match op {
+        0x20 | 0x28 | 0x30 | 0x38 => Some(3),
+        0xC0 | 0xC8 | 0xD0 | 0xD8 => Some(5),
+        0xC2 | 0xCA | 0xD2 | 0xDA => Some(4),
+        0xC4 | 0xCC | 0xD4 | 0xDC => Some(6),
+        _ => None,
+    };
+    i.target = match op {
+        0x18 | 0x20 | 0x28 | 0x30 | 0x38 => Some(relative),
+        0xC2 | 0xC3 | 0xC4 | 0xCA | 0xCC | 0xCD | 0xD2 | 0xD4 | 0xDA | 0xDC => Some(d16),
+        0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => Some((y * 8) as u16),
+        _ => None,
+    };
+    i.io_register = match op {
+        0xE0 | 0xF0 => io_register_name(0xFF00 | d8 as u16),
+        0xEA | 0xFA => io_register_name(d16),
+        _ => None,
+    };
+    i
+}
+
+fn decode_cb(addr: u16, op: u8) -> Instruction {
+    let (x, y, z) = (op >> 6, (op >> 3) & 7, op as usize & 7);
+    let (mnemonic, operands) = match x {
+        0 => (ROT[y as usize], String::from(R[z])),
+        1 => ("BIT", format!("{},{}", y, R[z])),
+        2 => ("RES", format!("{},{}", y, R[z])),
+        _ => ("SET", format!("{},{}", y, R[z])),
+    };
+    let cycles = match (x, z) {
+        (1, 6) => 3,
+        (_, 6) => 4,
+        _ => 2,
+    };
+    Instruction {
+        addr,
+        length: 2,
+        mnemonic,
+        operands,
+        cycles,
+        cycles_taken: None,
+        target: None,
+        io_register: None,
+    }
+}
+
+// Decodes an instruction from a ROM image, with bank 0 at $0000-$3FFF and `bank` switched into
+// $4000-$7FFF. Bytes past the end of the ROM read as $FF.
+pub fn decode_rom(rom: &[u8], bank: usize, addr: u16) -> Instruction {
+    decode(addr, |a| rom_byte(rom, bank, a))
+}
+
+// Reads a ROM image as decode_rom sees it.
+pub fn rom_byte(rom: &[u8], bank: usize, addr: u16) -> u8 {
+    let offset = match addr {
+        0x0000..=0x3FFF => addr as usize,
+        0x4000..=0x7FFF => bank * 0x4000 + (addr as usize - 0x4000),
+        _ => usize::MAX,
+    };
+    rom.get(offset).copied().unwrap_or(0xFF)
+}
+
+// Decodes an instruction from the current memory map without triggering watchpoints.
+pub fn decode_live(cpu: &Cpu, addr: u16) -> Instruction {
+    decode(addr, |a| cpu.mmu.peek(a))
+}
+
+// Name of the hardware register at `addr`, as used in Pan Docs.
+pub fn io_register_name(addr: u16) -> Option<&'static str> {
+    Some(match addr {
+        0xFF00 => "P1",
+        0xFF01 => "SB",
+        0xFF02 => "SC",
+        0xFF04 => "DIV",
+        0xFF05 => "TIMA",
+        0xFF06 => "TMA",
+        0xFF07 => "TAC",
+        0xFF0F => "IF",
+        0xFF10 => "NR10",
+        0xFF11 => "NR11",
+        0xFF12 => "NR12",
+        0xFF13 => "NR13",
+        0xFF14 => "NR14",
+        0xFF16 => "NR21",
+        0xFF17 => "NR22",
+        0xFF18 => "NR23",
+        0xFF19 => "NR24",
+        0xFF1A => "NR30",
+        0xFF1B => "NR31",
+        0xFF1C => "NR32",
+        0xFF1D => "NR33",
+        0xFF1E => "NR34",
+        0xFF20 => "NR41",
+        0xFF21 => "NR42",
+        0xFF22 => "NR43",
+        0xFF23 => "NR44",
+        0xFF24 => "NR50",
+        0xFF25 => "NR51",
+        0xFF26 => "NR52",
+        0xFF30..=0xFF3F => "WAVE",
+        0xFF40 => "LCDC",
+        0xFF41 => "STAT",
+        0xFF42 => "SCY",
+        0xFF43 => "SCX",
+        0xFF44 => "LY",
+        0xFF45 => "LYC",
+        0xFF46 => "DMA",
+        0xFF47 => "BGP",
+        0xFF48 => "OBP0",
+        0xFF49 => "OBP1",
+        0xFF4A => "WY",
+        0xFF4B => "WX",
+        0xFF4D => "KEY1",
+        0xFF4F => "VBK",
+        0xFF50 => "BOOT",
+        0xFF51 => "HDMA1",
+        0xFF52 => "HDMA2",
+        0xFF53 => "HDMA3",
+        0xFF54 => "HDMA4",
+        0xFF55 => "HDMA5",
+        0xFF56 => "RP",
+        0xFF68 => "BCPS",
+        0xFF69 => "BCPD",
+        0xFF6A => "OCPS",
+        0xFF6B => "OCPD",
+        0xFF70 => "SVBK",
+        0xFFFF => "IE",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn decode_bytes(addr: u16, bytes: &[u8]) -> Instruction {
+        decode(addr, |a| bytes.get(a.wrapping_sub(addr) as usize).copied().unwrap_or(0))
+    }
+
+    #[test]
+    fn base_opcodes() {
+        let cases: [(&[u8], &str, u8, u8); 10] = [
+            (&[0x00], "NOP", 1, 1),
+            (&[0x01, 0x34, 0x12], "LD BC,$1234", 3, 3),
+            (&[0x22], "LD (HL+),A", 1, 2),
+            (&[0x34], "INC (HL)", 1, 3),
+            (&[0x46], "LD B,(HL)", 1, 2),
+            (&[0x9E], "SBC A,(HL)", 1, 2),
+            (&[0xE8, 0xFE], "ADD SP,-2", 2, 4),
+            (&[0xF8, 0x05], "LD HL,SP+5", 2, 3),
+            (&[0xE2], "LD ($FF00+C),A", 1, 2),
+            (&[0xD3], "DB $D3", 1, 1),
+        ];
+        for (bytes, text, length, cycles) in cases {
+            let i = decode_bytes(0x0100, bytes);
+            assert_eq!((i.to_string().as_str(), i.length, i.cycles), (text, length, cycles));
+        }
+    }
+
+    #[test]
+    fn cb_opcodes() {
+        assert_eq!(decode_bytes(0, &[0xCB, 0x37]).to_string(), "SWAP A");
+        let i = decode_bytes(0, &[0xCB, 0x7E]);
+        assert_eq!((i.to_string().as_str(), i.length, i.cycles), ("BIT 7,(HL)", 2, 3));
+        assert_eq!(decode_bytes(0, &[0xCB, 0xC6]).cycles, 4);
+    }
+
+    #[test]
+    fn jumps_and_io_registers() {
+        let i = decode_bytes(0x0150, &[0x20, 0xFE]);
+        assert_eq!(i.to_string(), "JR NZ,$0150");
+        assert_eq!((i.cycles, i.cycles_taken, i.target), (2, Some(3), Some(0x0150)));
+        let label = |addr| (addr == 0x0150).then(|| String::from("Main"));
+        assert_eq!(i.text_with(label), "JR NZ,Main");
+
+        let i = decode_bytes(0, &[0xE0, 0x40]);
+        assert_eq!(i.io_register, Some("LCDC"));
+        assert!(i.to_string().ends_with("; LCDC"));
+    }
+
+    #[test]
+    fn rom_banks() {
+        let mut rom = vec![0; 0x10000];
+        rom[0x4000 * 3 + 0x10] = 0xC9;
+        assert_eq!(decode_rom(&rom, 3, 0x4010).mnemonic, "RET");
+        assert_eq!(decode_rom(&rom, 1, 0x4010).mnemonic, "NOP");
+        assert_eq!(decode_rom(&rom, 9, 0x4010).to_string(), "RST $38");
+    }
+}
@@ -0,0 +1,45 @@
+use std::fmt;
+
+// Something the emulator can't carry on from. These come from the game rather than from bugs in
+// the emulator, so frontends should report them instead of crashing.
+//
+// UnimplementedOpcode stops before the instruction, with nothing else run. The bank switch error
+// comes after the instruction that caused it and the rest of the hardware has run for it, so a
+// frontend can log it and carry on. is_fatal tells the two kinds apart.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    // The ROM doesn't fit in the memory the MMU has for it.
+    RomTooLarge { size: usize, max: usize },
+    // An opcode the CPU doesn't have, which locks up real hardware. PC is left pointing at it.
+    UnimplementedOpcode { addr: u16, opcode: u8, prefixed: bool },
+    // A write to 0x4000-0x5FFF, which needs RAM banking or a larger MBC than is supported. The
+    // write is ignored.
+    UnsupportedBankSwitch { addr: u16, value: u8 },
+}
+
+impl Error {
+    // Whether emulation can't go on after this error. The others only mean something the game
+    // asked for was ignored.
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, Error::RomTooLarge { .. } | Error::UnimplementedOpcode { .. })
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::RomTooLarge { size, max } => write!(f, "ROM is {} bytes, but at most {} are supported", size, max),
+            Error::UnimplementedOpcode { addr, opcode, prefixed: false } => {
+                write!(f, "unimplemented opcode ${:02X} at ${:04X}", opcode, addr)
+            }
+            Error::UnimplementedOpcode { addr, opcode, prefixed: true } => {
+                write!(f, "unimplemented opcode $CB ${:02X} at ${:04X}", opcode, addr)
+            }
+            Error::UnsupportedBankSwitch { addr, value } => {
+                write!(f, "unsupported RAM or ROM bank switch, ${:02X} written to ${:04X}", value, addr)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,51 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
+
+// Writes an 8-bit RGB image, laid out like the PPU's screen buffer, to a PNG file.
+pub fn save_png<P: AsRef<Path>>(path: P, width: u32, height: u32, rgb: &[u8]) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(to_io)?;
+    writer.write_image_data(rgb).map_err(to_io)?;
+    Ok(())
+}
+
+fn to_io(e: png::EncodingError) -> io::Error {
+    io::Error::other(e)
+}
+
+// Reads a PNG file as 8-bit RGB, returning its width, height and pixels. Grayscale and alpha
+// images are converted, with alpha ignored.
+pub fn load_png<P: AsRef<Path>>(path: P) -> io::Result<(u32, u32, Vec<u8>)> {
+    let mut decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().map_err(io::Error::other)?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).map_err(io::Error::other)?;
+    let pixels = &buf[..info.buffer_size()];
+    let rgb = match info.color_type {
+        png::ColorType::Rgb => pixels.to_vec(),
+        png::ColorType::Rgba => pixels.chunks_exact(4).flat_map(|p| [p[0], p[1], p[2]]).collect(),
+        png::ColorType::Grayscale => pixels.iter().flat_map(|&p| [p; 3]).collect(),
+        png::ColorType::GrayscaleAlpha => pixels.chunks_exact(2).flat_map(|p| [p[0]; 3]).collect(),
+        png::ColorType::Indexed => return Err(io::Error::other("unexpanded indexed PNG")),
+    };
+    Ok((info.width, info.height, rgb))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn png_round_trip() {
+        let path = std::env::temp_dir().join(format!("gebb_image_{}.png", std::process::id()));
+        let rgb: Vec<u8> = (0..4 * 2 * 3).map(|i| i as u8 * 10).collect();
+        save_png(&path, 4, 2, &rgb).unwrap();
+        assert_eq!(load_png(&path).unwrap(), (4, 2, rgb));
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -1,58 +1,189 @@
+use crate::state::{StateError, StateReader, StateWriter};
+
+// Bit positions match the layout of the joypad's internal button state.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Button {
+    Right = 0,
+    Left = 1,
+    Up = 2,
+    Down = 3,
+    A = 4,
+    B = 5,
+    Select = 6,
+    Start = 7,
+}
+
+impl Button {
+    pub const ALL: [Button; 8] = [
+        Button::Right,
+        Button::Left,
+        Button::Up,
+        Button::Down,
+        Button::A,
+        Button::B,
+        Button::Select,
+        Button::Start,
+    ];
+
+    // Bit for this button in a mask passed to `Joypad::set_all`.
+    pub fn mask(self) -> u8 {
+        1 << self as u8
+    }
+}
+
 pub struct Joypad {
-    keysA: u8,
-    keysD: u8,
-    pub select: bool // When false, keysD. When true, keysA
+    held: u8,    // Buttons reported by the frontend, one bit per `Button`. 1 means pressed.
+    pressed: u8, // `held` after filtering, which is what the game sees.
+    select: u8,  // P14 (bit 4) and P15 (bit 5) as last written. A row is selected when its bit is 0.
+    pub filter_opposite: bool, // Hides Left+Right and Up+Down, which can't be pressed on real hardware.
+    pub interrupt: u8,
 }
 
 impl Joypad {
     pub fn new() -> Self {
         Self {
-            keysA: 0xFF, // 1 Means unpressed.
-            keysD: 0xFF,
-            select: false,
+            held: 0,
+            pressed: 0,
+            select: 0x30,
+            filter_opposite: true,
+            interrupt: 0,
         }
     }
 
-    pub fn down(&mut self, key: u8) { // TODO: Do this using pointers like I originally planned (idk how though).
-        // let mut orig = if self.select {&self.keysD} else {&self.keysA};
-        // orig = &self.set(key, *orig);
-        if self.select {
-            self.keysD = self.set(key, self.keysD);
+    pub fn set(&mut self, button: Button, pressed: bool) {
+        if pressed {
+            self.set_all(self.held | button.mask());
         } else {
-            self.keysA = self.set(key, self.keysA);
+            self.set_all(self.held & !button.mask());
         }
     }
 
-    pub fn up(&mut self, key: u8) {
-        if self.select {
-            self.keysD = self.res(key, self.keysD);
-        } else {
-            self.keysA = self.res(key, self.keysA);
+    // Replaces the state of every button at once, built from `Button::mask`.
+    pub fn set_all(&mut self, mask: u8) {
+        self.held = mask;
+        self.update(self.filtered(), self.select);
+    }
+
+    // Buttons currently held by the frontend, before filtering.
+    pub fn held(&self) -> u8 {
+        self.held
+    }
+
+    // Opposite directions pressed together cancel out.
+    fn filtered(&self) -> u8 {
+        let mut pressed = self.held;
+        if self.filter_opposite {
+            for pair in [Button::Right.mask() | Button::Left.mask(), Button::Up.mask() | Button::Down.mask()] {
+                if pressed & pair == pair {
+                    pressed &= !pair;
+                }
+            }
         }
+        pressed
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.held);
+        w.u8(self.pressed);
+        w.u8(self.select);
+        w.u8(self.interrupt);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.held = r.u8()?;
+        self.pressed = r.u8()?;
+        self.select = r.u8()?;
+        self.interrupt = r.u8()?;
+        Ok(())
     }
 
     pub fn write(&mut self, byte: u8) {
-        if (byte >> 5) & 0b1 == 0 {
-            self.select = false;
-        } else if (byte >> 4) & 0b1 == 1 {
-            self.select = true;
-        }
+        self.update(self.pressed, byte & 0x30);
     }
 
     pub fn read(&self) -> u8 {
-        
-        if self.select {
-            self.keysA
-        } else {
-            self.keysD
+        0xC0 | self.select | self.lines(self.pressed, self.select)
+    }
+
+    // Applies a new button and selection state, requesting an interrupt if any input line goes
+    // from high to low.
+    fn update(&mut self, pressed: u8, select: u8) {
+        let before = self.lines(self.pressed, self.select);
+        let after = self.lines(pressed, select);
+        if before & !after != 0 {
+            self.interrupt |= 0x10;
+        }
+        self.pressed = pressed;
+        self.select = select;
+    }
+
+    // The P10-P13 input lines. Every selected row pulls its pressed buttons low, so with both rows
+    // selected the two are combined and with neither every line reads high.
+    fn lines(&self, pressed: u8, select: u8) -> u8 {
+        let mut low = 0;
+        if select & 0x10 == 0 {
+            low |= pressed & 0x0F;
+        }
+        if select & 0x20 == 0 {
+            low |= pressed >> 4;
         }
+        !low & 0x0F
+    }
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rows_are_selected_independently() {
+        let mut joypad = Joypad::new();
+        joypad.set(Button::Right, true);
+        joypad.set(Button::B, true);
+
+        assert_eq!(joypad.read(), 0xFF);
+        joypad.write(0x20);
+        assert_eq!(joypad.read(), 0xEE);
+        joypad.write(0x10);
+        assert_eq!(joypad.read(), 0xDD);
+        joypad.write(0x00);
+        assert_eq!(joypad.read(), 0xCC);
+
+        joypad.set(Button::Right, false);
+        assert_eq!(joypad.read(), 0xCD);
     }
 
-    fn res(&self, position: u8, val: u8) -> u8 { // Set bit to 1
-        val & !(1 << position) | (u8::from(0) << position)
+    #[test]
+    fn interrupt_on_high_to_low() {
+        let mut joypad = Joypad::new();
+        joypad.set(Button::A, true); // While no row is selected
+        assert_eq!(joypad.interrupt, 0);
+
+        joypad.write(0x10);
+        assert_eq!(joypad.interrupt, 0x10);
+
+        joypad.interrupt = 0;
+        joypad.set(Button::A, false);
+        assert_eq!(joypad.interrupt, 0);
+        joypad.set(Button::Start, true);
+        assert_eq!(joypad.interrupt, 0x10);
     }
 
-    fn set(&self, position: u8, val: u8) -> u8 { // Set bit to 0
-        val & !(1 << position) | (u8::from(1) << position)
+    #[test]
+    fn opposite_directions_are_filtered() {
+        let mut joypad = Joypad::new();
+        joypad.write(0x20);
+        joypad.set_all(Button::Left.mask() | Button::Right.mask() | Button::Up.mask());
+        assert_eq!(joypad.read() & 0x0F, 0x0B);
+
+        joypad.filter_opposite = false;
+        joypad.set_all(Button::Left.mask() | Button::Right.mask());
+        assert_eq!(joypad.read() & 0x0F, 0x0C);
     }
-}
\ No newline at end of file
+}
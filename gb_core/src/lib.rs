@@ -1,6 +1,22 @@
 use cpu::Cpu;
+pub use error::Error;
 pub mod cpu;
+pub mod error;
 pub mod mbc;
 pub mod ppu;
 pub mod timer;
 pub mod joypad;
+pub mod serial;
+pub mod link;
+pub mod printer;
+pub mod image;
+pub mod state;
+pub mod rewind;
+pub mod movie;
+pub mod debugger;
+pub mod disasm;
+pub mod trace;
+pub mod symbols;
+pub mod memview;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
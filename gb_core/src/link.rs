@@ -0,0 +1,395 @@
+use crate::serial::{SerialLink, TICKS_PER_BYTE};
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+// Every message on the wire is a tag, a byte and the sender's time in ticks since the cable was
+// plugged in, little endian.
+const MESSAGE_LEN: usize = 10;
+pub type Message = [u8; MESSAGE_LEN];
+
+const TIME: u8 = 0; // How far the sender has run. It won't start a transfer any earlier.
+const START: u8 = 1; // The sender started a transfer with its own clock.
+const REPLY: u8 = 2; // The byte shifted back when the other side's transfer ended.
+
+// How often each side tells the other how far it has run, besides whenever it has to wait.
+const TIME_INTERVAL: u64 = 1024;
+
+fn message(tag: u8, byte: u8, time: u64) -> Message {
+    let mut msg = [0; MESSAGE_LEN];
+    msg[0] = tag;
+    msg[1] = byte;
+    msg[2..].copy_from_slice(&time.to_le_bytes());
+    msg
+}
+
+// Moves link messages between two emulator instances.
+pub trait Wire: Send {
+    fn send(&mut self, msg: Message);
+    // Returns the next message if one has arrived, without waiting.
+    fn try_recv(&mut self) -> Option<Message>;
+    // False once the other end has gone away.
+    fn connected(&self) -> bool;
+}
+
+// In-memory wire, used to connect two Cpus in the same process.
+pub struct ChannelWire {
+    tx: Sender<Message>,
+    rx: Receiver<Message>,
+    closed: bool,
+}
+
+impl Wire for ChannelWire {
+    fn send(&mut self, msg: Message) {
+        let _ = self.tx.send(msg);
+    }
+
+    fn try_recv(&mut self) -> Option<Message> {
+        match self.rx.try_recv() {
+            Ok(msg) => Some(msg),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => {
+                self.closed = true;
+                None
+            }
+        }
+    }
+
+    fn connected(&self) -> bool {
+        !self.closed
+    }
+}
+
+// A non-blocking socket. Partial messages are buffered until all of their bytes have arrived.
+pub struct StreamWire<S> {
+    stream: S,
+    buf: Vec<u8>,
+    // Set on end of file or an error other than having nothing to read yet.
+    closed: bool,
+}
+
+impl<S: Read + Write + Send> Wire for StreamWire<S> {
+    fn send(&mut self, msg: Message) {
+        let _ = self.stream.write_all(&msg);
+        let _ = self.stream.flush();
+    }
+
+    fn try_recv(&mut self) -> Option<Message> {
+        while self.buf.len() < MESSAGE_LEN {
+            let mut bytes = [0; MESSAGE_LEN];
+            match self.stream.read(&mut bytes[..MESSAGE_LEN - self.buf.len()]) {
+                Ok(n) if n > 0 => self.buf.extend_from_slice(&bytes[..n]),
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted) => return None,
+                Ok(_) | Err(_) => {
+                    self.closed = true;
+                    return None;
+                }
+            }
+        }
+        let msg = self.buf[..].try_into().unwrap();
+        self.buf.clear();
+        Some(msg)
+    }
+
+    fn connected(&self) -> bool {
+        !self.closed
+    }
+}
+
+// A link cable between two Game Boys, run in lockstep so transfers only depend on emulated time.
+//
+// Both sides count the ticks they have run since the cable was plugged in and regularly tell each
+// other. Neither gets more than a byte period ahead of what it has heard from the other, so a
+// transfer started with the internal clock at tick T is always known to the other side by the time
+// it reaches T plus a byte period. At that tick the other side shifts in the byte if it is waiting
+// on an external clock, and replies with its own or 0xFF otherwise. The side driving the clock
+// waits for that reply when its transfer ends. Nothing depends on wall clock time, so the same
+// inputs always give the same transfers however fast either emulator runs.
+pub struct LinkCable<W> {
+    wire: W,
+    // Ticks run since the cable was plugged in.
+    now: u64,
+    // Last time sent to the other side, and the furthest it has said it has run.
+    sent_time: u64,
+    peer_time: u64,
+    // Transfers started by the other side that haven't been answered, with their start times.
+    incoming: VecDeque<(u64, u8)>,
+    // Replies still owed for our own transfers, and the last one received.
+    awaiting: u32,
+    reply: Option<u8>,
+}
+
+impl<W: Wire> LinkCable<W> {
+    pub fn new(wire: W) -> Self {
+        Self {
+            wire,
+            now: 0,
+            sent_time: 0,
+            peer_time: 0,
+            incoming: VecDeque::new(),
+            awaiting: 0,
+            reply: None,
+        }
+    }
+
+    fn receive(&mut self, msg: Message) {
+        let time = u64::from_le_bytes(msg[2..].try_into().unwrap());
+        match msg[0] {
+            TIME => self.peer_time = self.peer_time.max(time),
+            START => {
+                self.peer_time = self.peer_time.max(time);
+                self.incoming.push_back((time, msg[1]));
+            }
+            REPLY => {
+                self.awaiting = self.awaiting.saturating_sub(1);
+                self.reply = Some(msg[1]);
+            }
+            _ => {}
+        }
+    }
+
+    // Handles the next message, waiting for one to arrive. Returns false once the other side has
+    // gone away, after which nothing will arrive.
+    fn wait(&mut self) -> bool {
+        loop {
+            if let Some(msg) = self.wire.try_recv() {
+                self.receive(msg);
+                return true;
+            }
+            if !self.wire.connected() {
+                return false;
+            }
+            thread::sleep(Duration::from_micros(50));
+        }
+    }
+
+    fn send_time(&mut self) {
+        if self.sent_time != self.now {
+            self.wire.send(message(TIME, 0, self.now));
+            self.sent_time = self.now;
+        }
+    }
+
+    // Answers the other side's transfers that ended by `time` without anything listening here.
+    fn reply_unclaimed(&mut self, time: u64) {
+        while let Some(&(start, _)) = self.incoming.front() {
+            if start + TICKS_PER_BYTE as u64 > time {
+                break;
+            }
+            self.incoming.pop_front();
+            self.wire.send(message(REPLY, 0xFF, time));
+        }
+    }
+}
+
+impl<W: Wire> SerialLink for LinkCable<W> {
+    fn tick(&mut self, ticks: u32) {
+        // Transfers that ended on the last tick were offered to `external` then.
+        self.reply_unclaimed(self.now);
+        self.now += ticks as u64;
+        if self.now >= self.sent_time + TIME_INTERVAL {
+            self.send_time();
+        }
+        // Wait until every transfer the other side starts that would end by now is known.
+        while self.peer_time + TICKS_PER_BYTE as u64 <= self.now {
+            self.send_time();
+            if !self.wait() {
+                break;
+            }
+        }
+    }
+
+    fn send(&mut self, byte: u8) {
+        self.awaiting += 1;
+        self.wire.send(message(START, byte, self.now));
+        self.sent_time = self.now;
+    }
+
+    fn recv(&mut self) -> Option<u8> {
+        // This side is driving its own clock, so it isn't listening for the other side's transfers.
+        self.reply_unclaimed(self.now);
+        self.send_time();
+        while self.awaiting > 0 {
+            // Once the other side is gone, transfers finish straight away like with no cable.
+            if !self.wait() {
+                self.awaiting = 0;
+                self.reply = None;
+            }
+        }
+        Some(self.reply.take().unwrap_or(0xFF))
+    }
+
+    fn external(&mut self, byte: u8) -> Option<u8> {
+        let &(start, incoming) = self.incoming.front()?;
+        if start + TICKS_PER_BYTE as u64 > self.now {
+            return None;
+        }
+        self.incoming.pop_front();
+        self.wire.send(message(REPLY, byte, self.now));
+        Some(incoming)
+    }
+}
+
+// Two ends of an in-memory cable. Each end waits for the other, so the Cpus must run on separate
+// threads.
+pub fn channel_pair() -> (LinkCable<ChannelWire>, LinkCable<ChannelWire>) {
+    let (tx_a, rx_a) = mpsc::channel();
+    let (tx_b, rx_b) = mpsc::channel();
+    (
+        LinkCable::new(ChannelWire { tx: tx_a, rx: rx_b, closed: false }),
+        LinkCable::new(ChannelWire { tx: tx_b, rx: rx_a, closed: false }),
+    )
+}
+
+fn stream_link<S: Read + Write + Send>(stream: S) -> LinkCable<StreamWire<S>> {
+    LinkCable::new(StreamWire { stream, buf: Vec::new(), closed: false })
+}
+
+// Waits for the other instance to connect on `addr`.
+pub fn tcp_listen<A: ToSocketAddrs>(addr: A) -> io::Result<LinkCable<StreamWire<TcpStream>>> {
+    let (stream, _) = TcpListener::bind(addr)?.accept()?;
+    tcp_link(stream)
+}
+
+pub fn tcp_connect<A: ToSocketAddrs>(addr: A) -> io::Result<LinkCable<StreamWire<TcpStream>>> {
+    tcp_link(TcpStream::connect(addr)?)
+}
+
+fn tcp_link(stream: TcpStream) -> io::Result<LinkCable<StreamWire<TcpStream>>> {
+    stream.set_nodelay(true)?;
+    stream.set_nonblocking(true)?;
+    Ok(stream_link(stream))
+}
+
+// Waits for the other instance to connect to the socket at `path`. A socket left there by an
+// earlier run that nothing listens on any more is replaced, but anything else is left alone.
+#[cfg(unix)]
+pub fn unix_listen<P: AsRef<Path>>(path: P) -> io::Result<LinkCable<StreamWire<UnixStream>>> {
+    let stale = std::fs::symlink_metadata(&path).is_ok_and(|m| m.file_type().is_socket())
+        && UnixStream::connect(&path).is_err();
+    if stale {
+        std::fs::remove_file(&path)?;
+    }
+    let (stream, _) = UnixListener::bind(path)?.accept()?;
+    stream.set_nonblocking(true)?;
+    Ok(stream_link(stream))
+}
+
+#[cfg(unix)]
+pub fn unix_connect<P: AsRef<Path>>(path: P) -> io::Result<LinkCable<StreamWire<UnixStream>>> {
+    let stream = UnixStream::connect(path)?;
+    stream.set_nonblocking(true)?;
+    Ok(stream_link(stream))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::Cpu;
+    use crate::test_util::{spawn_with_stack, with_stack};
+
+    // After `delay` NOPs, writes `data` to SB, starts a transfer with the given SC value and spins.
+    fn transfer_rom(data: u8, control: u8, delay: usize) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        let program = [0x3E, data, 0xE0, 0x01, 0x3E, control, 0xE0, 0x02, 0x18, 0xFE];
+        rom[0x100 + delay..0x100 + delay + program.len()].copy_from_slice(&program);
+        rom
+    }
+
+    // Runs two linked Cpus on their own threads for `cycles` instructions each, returning SB and
+    // whether the serial interrupt was requested on each side.
+    fn run_linked(master: Vec<u8>, slave: Vec<u8>, cycles: usize) -> [(u8, bool); 2] {
+        let (a, b) = channel_pair();
+        let run = |rom: Vec<u8>, link: LinkCable<ChannelWire>| {
+            spawn_with_stack(move || {
+                let mut cpu = Cpu::new();
+                cpu.load(&rom).unwrap();
+                cpu.set_serial_link(Box::new(link));
+                for _ in 0..cycles {
+                    cpu.do_cycle().unwrap();
+                }
+                (cpu.mmu.read_byte(0xFF01), cpu.mmu.intf & 0x08 != 0)
+            })
+        };
+        let master = run(master, a);
+        let slave = run(slave, b);
+        [master.join().unwrap(), slave.join().unwrap()]
+    }
+
+    #[test]
+    fn channel_pair_exchanges_bytes() {
+        let result = run_linked(transfer_rom(0x12, 0x81, 0), transfer_rom(0x34, 0x80, 0), 4000);
+        assert_eq!(result, [(0x34, true), (0x12, true)]);
+    }
+
+    #[test]
+    fn transfers_only_depend_on_emulated_time() {
+        // The slave starts listening a few hundred ticks after the master's transfer has ended, no
+        // matter how the two threads happen to be scheduled.
+        for _ in 0..5 {
+            let result = run_linked(transfer_rom(0x12, 0x81, 0), transfer_rom(0x34, 0x80, 1100), 4000);
+            assert_eq!(result, [(0xFF, true), (0x34, false)]);
+        }
+        let result = run_linked(transfer_rom(0x12, 0x81, 0), transfer_rom(0x34, 0x80, 900), 4000);
+        assert_eq!(result, [(0x34, true), (0x12, true)]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket_exchanges_bytes() {
+        let (a, b) = UnixStream::pair().unwrap();
+        a.set_nonblocking(true).unwrap();
+        b.set_nonblocking(true).unwrap();
+        let slave = spawn_with_stack(move || {
+            let mut slave = Cpu::new();
+            slave.load(&transfer_rom(0x34, 0x80, 0)).unwrap();
+            slave.set_serial_link(Box::new(stream_link(b)));
+            while slave.mmu.intf & 0x08 == 0 {
+                slave.do_cycle().unwrap();
+            }
+            slave.mmu.read_byte(0xFF01)
+        });
+        with_stack(move || {
+            let mut master = Cpu::new();
+            master.load(&transfer_rom(0x12, 0x81, 0)).unwrap();
+            master.set_serial_link(Box::new(stream_link(a)));
+            while master.mmu.intf & 0x08 == 0 {
+                master.do_cycle().unwrap();
+            }
+            assert_eq!(master.mmu.read_byte(0xFF01), 0x34);
+        });
+        assert_eq!(slave.join().unwrap(), 0x12);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn disconnected_peer_stops_blocking() {
+        let (a, b) = UnixStream::pair().unwrap();
+        a.set_nonblocking(true).unwrap();
+        drop(b);
+        let mut link = stream_link(a);
+        link.tick(TICKS_PER_BYTE * 4);
+        link.send(0x12);
+        assert_eq!(link.recv(), Some(0xFF));
+        assert_eq!(link.recv(), Some(0xFF));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn listen_leaves_other_files_alone() {
+        let path = std::env::temp_dir().join(format!("gebb-link-{}", std::process::id()));
+        std::fs::write(&path, b"keep").unwrap();
+        assert!(unix_listen(&path).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"keep");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
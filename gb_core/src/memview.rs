@@ -0,0 +1,139 @@
+use crate::cpu::mmu::MMU;
+use std::fmt;
+
+const ROW_LEN: usize = 16;
+
+// One line of a memory view: up to 16 bytes starting at `addr`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Row {
+    pub addr: u16,
+    // The bank mapped at `addr`, for the switchable ROM, cartridge RAM and WRAM regions.
+    pub bank: Option<u16>,
+    pub bytes: Vec<u8>,
+    // Which bytes changed since the view was last refreshed.
+    pub changed: Vec<bool>,
+}
+
+impl Row {
+    // Formats the row as hex and ASCII, like
+    //
+    //     01:4000  3E 4F E0 01 3E 81 E0 02 3E 4B E0 01 3E 81 E0 02  >O..>...>K..>...
+    //
+    // with changed bytes wrapped in the `highlight` start and end markers, such as terminal colour
+    // codes.
+    pub fn text(&self, highlight: (&str, &str)) -> String {
+        let mut text = match self.bank {
+            Some(bank) => format!("{:02X}:{:04X} ", bank, self.addr),
+            None => format!("   {:04X} ", self.addr),
+        };
+        let mut ascii = String::new();
+        for (i, byte) in self.bytes.iter().enumerate() {
+            let (start, end) = if self.changed[i] { highlight } else { ("", "") };
+            text += &format!(" {}{:02X}{}", start, byte, end);
+            let c = if byte.is_ascii_graphic() || *byte == b' ' { *byte as char } else { '.' };
+            ascii += &format!("{}{}{}", start, c, end);
+        }
+        text += &"   ".repeat(ROW_LEN - self.bytes.len());
+        format!("{}  {}", text, ascii)
+    }
+}
+
+impl fmt::Display for Row {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.text(("", "")))
+    }
+}
+
+fn bank(mmu: &MMU, addr: u16) -> Option<u16> {
+    match addr {
+        0x4000..=0x7FFF | 0xA000..=0xBFFF | 0xD000..=0xDFFF => Some(mmu.bank_at(addr)),
+        _ => None,
+    }
+}
+
+// A region of the address space that remembers what it last showed, so frontends can highlight
+// the bytes that change between refreshes. Memory is read with MMU::peek, so viewing it has no
+// side effects.
+#[derive(Clone, Debug)]
+pub struct MemoryView {
+    start: u16,
+    len: usize,
+    previous: Vec<u8>,
+}
+
+impl MemoryView {
+    // The region is cut short at the end of the address space.
+    pub fn new(start: u16, len: usize) -> Self {
+        Self { start, len: len.min(0x10000 - start as usize), previous: Vec::new() }
+    }
+
+    pub fn start(&self) -> u16 {
+        self.start
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Reads the region again. Nothing counts as changed on the first refresh.
+    pub fn refresh(&mut self, mmu: &MMU) -> Vec<Row> {
+        let bytes: Vec<u8> = (0..self.len).map(|i| mmu.peek(self.start.wrapping_add(i as u16))).collect();
+        let rows = bytes
+            .chunks(ROW_LEN)
+            .enumerate()
+            .map(|(row, chunk)| {
+                let offset = row * ROW_LEN;
+                let addr = self.start.wrapping_add(offset as u16);
+                let changed = (offset..offset + chunk.len())
+                    .map(|i| self.previous.get(i).is_some_and(|&previous| previous != bytes[i]))
+                    .collect();
+                Row { addr, bank: bank(mmu, addr), bytes: chunk.to_vec(), changed }
+            })
+            .collect();
+        self.previous = bytes;
+        rows
+    }
+}
+
+// A one-off hex dump of `len` bytes from `start`.
+pub fn dump(mmu: &MMU, start: u16, len: usize) -> Vec<Row> {
+    MemoryView::new(start, len).refresh(mmu)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn highlights_changes() {
+        let mut mmu = MMU::new();
+        mmu.poke(0xC000, b'H');
+        mmu.poke(0xC001, b'i');
+        let mut view = MemoryView::new(0xC000, 20);
+        let rows = view.refresh(&mmu);
+        assert_eq!(rows.len(), 2);
+        assert!(rows[0].to_string().starts_with("   C000  48 69 FF"));
+        assert!(rows[0].to_string().ends_with("  Hi.............."));
+        assert_eq!(rows[1].bytes.len(), 4);
+
+        mmu.poke(0xC011, 0x20);
+        let rows = view.refresh(&mmu);
+        assert_eq!(rows[1].changed, [false, true, false, false]);
+        assert!(rows[1].text(("[", "]")).starts_with("   C010  FF [20] FF FF"));
+        assert!(view.refresh(&mmu).iter().all(|row| !row.changed.contains(&true)));
+    }
+
+    #[test]
+    fn shows_banks() {
+        let mmu = MMU::new();
+        let rows = dump(&mmu, 0x4000, 16);
+        assert!(rows[0].to_string().starts_with("01:4000  00"));
+        let rows = dump(&mmu, 0xDFF8, 16);
+        assert!(rows[0].to_string().starts_with("01:DFF8  FF FF FF FF FF FF FF FF FF FF"));
+        assert_eq!(dump(&mmu, 0xFFF8, 16)[0].bytes.len(), 8);
+    }
+}
@@ -0,0 +1,199 @@
+use crate::cpu::Cpu;
+use crate::error::Error;
+use crate::state::{StateError, StateReader, StateWriter};
+
+// Movie files start with this magic and the format version, followed by the ROM checksum, a flags
+// byte, the save state the movie starts from and one button mask per frame.
+const MAGIC: &[u8; 8] = b"GEBBMOV\0";
+const VERSION: u32 = 1;
+
+const FLAG_DPAD_FILTER: u8 = 0x01;
+
+// Per-frame joypad input together with everything needed to replay it deterministically.
+//
+// Input only changes between frames, and each frame is run with Cpu::run_frame, so replaying the
+// same masks from the same starting state always gives the same result. The emulator has no other
+// source of input: there is no real-time clock, and a serial link should be left disconnected
+// while recording.
+pub struct Movie {
+    pub rom_checksum: u32,
+    pub dpad_filter: bool,
+    pub initial_state: Vec<u8>,
+    pub frames: Vec<u8>, // Masks in the format taken by Cpu::set_buttons.
+}
+
+impl Movie {
+    // Starts an empty movie from the Cpu's current state.
+    pub fn new(cpu: &Cpu) -> Self {
+        Self {
+            rom_checksum: cpu.mmu.rom_checksum(),
+            dpad_filter: cpu.dpad_filter(),
+            initial_state: cpu.save_state(),
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        w.bytes(MAGIC);
+        w.u32(VERSION);
+        w.u32(self.rom_checksum);
+        w.u8(if self.dpad_filter { FLAG_DPAD_FILTER } else { 0 });
+        w.u32(self.initial_state.len() as u32);
+        w.bytes(&self.initial_state);
+        w.u32(self.frames.len() as u32);
+        w.bytes(&self.frames);
+        w.finish()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, StateError> {
+        let mut r = StateReader::new(data);
+        let mut magic = [0; 8];
+        r.bytes(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let version = r.u32()?;
+        if version != VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let rom_checksum = r.u32()?;
+        let dpad_filter = r.u8()? & FLAG_DPAD_FILTER != 0;
+        let initial_state = r.sized_bytes()?;
+        let frames = r.sized_bytes()?;
+        Ok(Self {
+            rom_checksum,
+            dpad_filter,
+            initial_state,
+            frames,
+        })
+    }
+
+    // Rewinds the Cpu to where the movie starts. Fails if it was recorded with another ROM.
+    pub fn start(&self, cpu: &mut Cpu) -> Result<(), StateError> {
+        let loaded = cpu.mmu.rom_checksum();
+        if loaded != self.rom_checksum {
+            return Err(StateError::WrongRom { expected: loaded, found: self.rom_checksum });
+        }
+        cpu.set_dpad_filter(self.dpad_filter);
+        cpu.load_state(&self.initial_state)
+    }
+}
+
+// Records the buttons held at the start of every frame.
+pub struct Recorder {
+    movie: Movie,
+}
+
+impl Recorder {
+    pub fn new(cpu: &Cpu) -> Self {
+        Self { movie: Movie::new(cpu) }
+    }
+
+    // Runs one frame with the buttons currently held and records them.
+    pub fn run_frame(&mut self, cpu: &mut Cpu) -> Result<u32, Error> {
+        self.movie.frames.push(cpu.buttons());
+        cpu.run_frame()
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    pub fn finish(self) -> Movie {
+        self.movie
+    }
+}
+
+// Replays a movie frame by frame, overriding any input from the frontend.
+pub struct Player {
+    movie: Movie,
+    frame: usize,
+}
+
+impl Player {
+    // Loads the movie's initial state into the Cpu.
+    pub fn new(movie: Movie, cpu: &mut Cpu) -> Result<Self, StateError> {
+        movie.start(cpu)?;
+        Ok(Self { movie, frame: 0 })
+    }
+
+    // Runs the next frame of the movie. Returns None once every frame has been played.
+    pub fn run_frame(&mut self, cpu: &mut Cpu) -> Result<Option<u32>, Error> {
+        let Some(&mask) = self.movie.frames.get(self.frame) else { return Ok(None) };
+        self.frame += 1;
+        cpu.set_buttons(mask);
+        cpu.run_frame().map(Some)
+    }
+
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn finished(&self) -> bool {
+        self.frame >= self.movie.frames.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::joypad::Button;
+
+    // Copies the joypad register into WRAM forever, so input shows up in memory.
+    fn input_rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        // LD A,$10; LDH ($00),A; LDH A,($00); LD ($C000),A; JR -11
+        let program = [0x3E, 0x10, 0xE0, 0x00, 0xF0, 0x00, 0xEA, 0x00, 0xC0, 0x18, 0xF5];
+        rom[0x100..0x100 + program.len()].copy_from_slice(&program);
+        rom
+    }
+
+    #[test]
+    fn playback_matches_recording() {
+        let mut cpu = Cpu::new();
+        cpu.load(&input_rom()).unwrap();
+        let mut recorder = Recorder::new(&cpu);
+        let mut recorded = Vec::new();
+        for frame in 0..6 {
+            cpu.set_button(Button::A, frame % 2 == 1);
+            recorder.run_frame(&mut cpu).unwrap();
+            recorded.push(cpu.mmu.read_byte(0xC000));
+        }
+        let end = cpu.save_state();
+        let data = recorder.finish().to_bytes();
+
+        cpu.set_button(Button::A, false);
+        cpu.run_frame().unwrap();
+        let mut player = Player::new(Movie::from_bytes(&data).unwrap(), &mut cpu).unwrap();
+        let mut replayed = Vec::new();
+        while player.run_frame(&mut cpu).unwrap().is_some() {
+            replayed.push(cpu.mmu.read_byte(0xC000));
+        }
+        assert_eq!(replayed, recorded);
+        assert_eq!(recorded[1] & 0x01, 0);
+        assert_eq!(cpu.save_state(), end);
+    }
+
+    #[test]
+    fn rejects_lengths_past_the_end() {
+        let data = Movie::new(&Cpu::new()).to_bytes();
+        // The initial state's length follows the magic, version, checksum and flags.
+        let mut corrupt = data.clone();
+        corrupt[17..21].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(Movie::from_bytes(&corrupt), Err(StateError::WrongSize)));
+        // The frame count comes last, with no frames after it.
+        let mut corrupt = data.clone();
+        corrupt[data.len() - 4..].copy_from_slice(&1000u32.to_le_bytes());
+        assert!(matches!(Movie::from_bytes(&corrupt), Err(StateError::WrongSize)));
+        assert!(Movie::from_bytes(&data).is_ok());
+    }
+
+    #[test]
+    fn refuses_other_rom() {
+        let mut cpu = Cpu::new();
+        let movie = Movie::new(&cpu);
+        cpu.load(&input_rom()).unwrap();
+        assert!(matches!(Player::new(movie, &mut cpu), Err(StateError::WrongRom { .. })));
+    }
+}
@@ -1,3 +1,17 @@
+use crate::state::{StateError, StateReader, StateWriter};
+
+// Screen colours of shades 0-3, lightest first.
+pub const SHADES: [(u8, u8, u8); 4] = [(255, 255, 255), (200, 200, 200), (100, 100, 100), (0, 0, 0)];
+
+// An entry in OAM, with the raw Y and X positions (offset by 16 and 8 from the screen).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sprite {
+    pub y: u8,
+    pub x: u8,
+    pub tile: u8,
+    pub flags: u8,
+}
+
 pub struct PPU {
     vram: [u8; 0x2000],
     oam: [u8; 0xA0],
@@ -15,6 +29,8 @@ pub struct PPU {
     pub screen_buffer: [u8; 160 * 144 * 3],
     pub updated: bool,
     pub interrupt: u8,
+    // Ticks spent in the current mode.
+    clock: u32,
 }
 
 impl PPU {
@@ -36,9 +52,107 @@ impl PPU {
             screen_buffer: [0; 160 * 144 * 3],
             updated: false,
             interrupt: 0,
+            clock: 0,
         }
     }
 
+    // Current STAT mode: 0 HBlank, 1 VBlank, 2 OAM search, 3 LCD transfer.
+    pub fn mode(&self) -> u8 {
+        self.stat & 0b11
+    }
+
+    // Read-only access for debugging tools like the tile and map viewers.
+    pub fn vram(&self) -> &[u8] {
+        &self.vram
+    }
+
+    pub fn oam(&self) -> &[u8] {
+        &self.oam
+    }
+
+    pub fn lcdc(&self) -> u8 {
+        self.lcdc
+    }
+
+    pub fn stat(&self) -> u8 {
+        self.stat
+    }
+
+    pub fn scroll(&self) -> (u8, u8) {
+        (self.scx, self.scy)
+    }
+
+    pub fn window_position(&self) -> (u8, u8) {
+        (self.wx, self.wy)
+    }
+
+    pub fn bgp(&self) -> u8 {
+        self.bgp
+    }
+
+    pub fn obp0(&self) -> u8 {
+        self.obp0
+    }
+
+    pub fn obp1(&self) -> u8 {
+        self.obp1
+    }
+
+    // Colour number 0-3 of pixel (x, y) in tile `index`, counting all 384 tiles from 0x8000.
+    pub fn tile_pixel(&self, index: usize, x: usize, y: usize) -> u8 {
+        let addr = index * 16 + y * 2;
+        let (lsb, msb) = (self.vram[addr], self.vram[addr + 1]);
+        let bit = 7 - x;
+        ((msb >> bit) & 1) << 1 | ((lsb >> bit) & 1)
+    }
+
+    // The tile shown at (x, y) of background map `map` (0 at 0x9800, 1 at 0x9C00), as an index
+    // for tile_pixel. Follows the tile data addressing currently selected by LCDC.
+    pub fn map_tile(&self, map: usize, x: usize, y: usize) -> usize {
+        let tile_id = self.vram[0x1800 + map * 0x400 + y * 32 + x];
+        if self.lcdc & 0b1_0000 != 0 {
+            tile_id as usize
+        } else {
+            (256 + (tile_id as i8) as isize) as usize
+        }
+    }
+
+    pub fn sprite(&self, n: usize) -> Sprite {
+        let entry = &self.oam[n * 4..n * 4 + 4];
+        Sprite { y: entry[0], x: entry[1], tile: entry[2], flags: entry[3] }
+    }
+
+    // The shade 0-3 `palette` gives colour number `colour`.
+    pub fn shade(palette: u8, colour: u8) -> u8 {
+        (palette >> (colour * 2)) & 0b11
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.bytes(&self.vram);
+        w.bytes(&self.oam);
+        for v in [self.lcdc, self.stat, self.scy, self.scx, self.ly, self.lyc, self.wy, self.wx, self.bgp, self.obp0, self.obp1] {
+            w.u8(v);
+        }
+        w.bytes(&self.screen_buffer);
+        w.bool(self.updated);
+        w.u8(self.interrupt);
+        w.u32(self.clock);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.bytes(&mut self.vram)?;
+        r.bytes(&mut self.oam)?;
+        for v in [&mut self.lcdc, &mut self.stat, &mut self.scy, &mut self.scx, &mut self.ly, &mut self.lyc, &mut self.wy, &mut self.wx, &mut self.bgp, &mut self.obp0, &mut self.obp1] {
+            *v = r.u8()?;
+        }
+        r.bytes(&mut self.screen_buffer)?;
+        self.updated = r.bool()?;
+        self.interrupt = r.u8()?;
+        self.clock = r.u32()?;
+        Ok(())
+    }
+
+    // Addresses the PPU doesn't handle read as 0xFF and ignore writes, like unmapped memory.
     pub fn read_byte(&self, address: u16) -> u8 {
         match address {
             0x8000..=0x9FFF => self.vram[(address - 0x8000) as usize],
@@ -54,7 +168,7 @@ impl PPU {
             0xFF49 => self.obp1,
             0xFF4A => self.wy,
             0xFF4B => self.wx,
-            _ => panic!("Invalid PPU read at address {:04x}", address),
+            _ => 0xFF,
         }
     }
 
@@ -73,11 +187,30 @@ impl PPU {
             0xFF49 => self.obp1 = value,
             0xFF4A => self.wy = value,
             0xFF4B => self.wx = value,
-            _ => panic!("Invalid PPU write at address {:04x}", address),
+            _ => {}
         }
     }
 
-    pub fn execute(&mut self) {
+    // Runs for `ticks` ticks, going through as many modes as that covers. A line takes 456 ticks:
+    // 80 in OAM search, 172 in LCD transfer and the rest in HBlank. VBlank lasts for 10 lines.
+    pub fn execute(&mut self, ticks: u32) {
+        self.clock += ticks;
+        loop {
+            let length = match self.mode() {
+                0 => 204,
+                1 => 456,
+                2 => 80,
+                _ => 172,
+            };
+            if self.clock < length {
+                break;
+            }
+            self.clock -= length;
+            self.next_mode();
+        }
+    }
+
+    fn next_mode(&mut self) {
         let current_mode = self.stat & 0b11;
 
         match current_mode {
@@ -240,14 +373,9 @@ impl PPU {
         background_buffer
     }
 
-    fn to_rgb(&self, colour: u8) -> (u8, u8, u8) {
-        match colour {
-            0 => (255, 255, 255),
-            1 => (200, 200, 200),
-            2 => (100, 100, 100),
-            3 => (0, 0, 0),
-            _ => {panic!("undefined colour {}", colour)}
-        }
+    // Screen colour of a shade 0-3.
+    pub fn to_rgb(&self, colour: u8) -> (u8, u8, u8) {
+        SHADES[colour.min(3) as usize]
     }
 
     fn render_sprites(&mut self) -> [u8; 144 * 160] {
@@ -307,4 +435,48 @@ impl PPU {
     }
     
     
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decodes_tiles_maps_and_sprites() {
+        let mut ppu = PPU::new();
+        // Tile 1 row 0: colours 3, 2, 1, 0, 0, 0, 0, 0. Tile 0x81 in the 0x8800 half is index 257.
+        ppu.write_byte(0x8010, 0b1010_0000);
+        ppu.write_byte(0x8011, 0b1100_0000);
+        ppu.write_byte(0x9800, 0x01);
+        ppu.write_byte(0x9C21, 0x81);
+        ppu.write_byte(0xFE04, 0x20);
+        ppu.write_byte(0xFE07, 0x10);
+
+        assert_eq!((0..4).map(|x| ppu.tile_pixel(1, x, 0)).collect::<Vec<_>>(), [3, 2, 1, 0]);
+        ppu.write_byte(0xFF40, 0x10);
+        assert_eq!(ppu.map_tile(0, 0, 0), 1);
+        assert_eq!(ppu.map_tile(1, 1, 1), 0x81);
+        ppu.write_byte(0xFF40, 0x00);
+        assert_eq!(ppu.map_tile(0, 0, 0), 257);
+        assert_eq!(ppu.map_tile(1, 1, 1), 129);
+        assert_eq!(ppu.sprite(1), Sprite { y: 0x20, x: 0, tile: 0, flags: 0x10 });
+        assert_eq!(PPU::shade(0xE4, 1), 1);
+        assert_eq!(PPU::shade(0x1B, 0), 3);
+    }
+
+    #[test]
+    fn modes_follow_ticks() {
+        let mut ppu = PPU::new();
+        ppu.execute(203);
+        assert_eq!((ppu.mode(), ppu.ly), (0, 0));
+        ppu.execute(1);
+        assert_eq!((ppu.mode(), ppu.ly), (2, 1));
+        ppu.execute(80);
+        assert_eq!(ppu.mode(), 3);
+        // One instruction can cover more than one mode.
+        ppu.execute(172 + 204 + 10);
+        assert_eq!((ppu.mode(), ppu.ly), (2, 2));
+        ppu.execute(456 * 142 - 10);
+        assert_eq!((ppu.mode(), ppu.ly), (1, 144));
+    }
+}
@@ -0,0 +1,346 @@
+use crate::image;
+use crate::serial::SerialLink;
+use std::path::PathBuf;
+
+const WIDTH: usize = 160;
+const TILES_PER_ROW: usize = WIDTH / 8;
+// Enough tile data for 9 DATA packets, which is what the printer can hold at once.
+const BUFFER_SIZE: usize = 0x280 * 9;
+
+const INIT: u8 = 0x01;
+const PRINT: u8 = 0x02;
+const DATA: u8 = 0x04;
+const STATUS: u8 = 0x0F;
+
+// Status bits
+const CHECKSUM_ERROR: u8 = 0x01;
+const BUSY: u8 = 0x02;
+const IMAGE_FULL: u8 = 0x04;
+const UNPROCESSED: u8 = 0x08;
+
+// Number of status requests the printer stays busy for after a PRINT command.
+const PRINT_BUSY_POLLS: u8 = 4;
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    Alive,
+    Status,
+}
+
+// Game Boy Printer on the other end of the link cable. Printed pages are saved as
+// print_NNNN.png in `out_dir`, numbered on from any printouts already there.
+pub struct Printer {
+    out_dir: PathBuf,
+    state: State,
+    command: u8,
+    compressed: bool,
+    length: u16,
+    packet: Vec<u8>,
+    checksum: u16,
+    expected_checksum: u16,
+    response: u8,
+    status: u8,
+    busy: u8,
+    image: Vec<u8>,
+    pages: usize,
+}
+
+impl Printer {
+    pub fn new<P: Into<PathBuf>>(out_dir: P) -> Self {
+        Self {
+            out_dir: out_dir.into(),
+            state: State::Magic1,
+            command: 0,
+            compressed: false,
+            length: 0,
+            packet: Vec::new(),
+            checksum: 0,
+            expected_checksum: 0,
+            response: 0,
+            status: 0,
+            busy: 0,
+            image: Vec::new(),
+            pages: 0,
+        }
+    }
+
+    // Feeds one byte from the Game Boy through the packet state machine and returns the byte the
+    // printer shifts back.
+    fn exchange(&mut self, byte: u8) -> u8 {
+        let mut response = 0;
+        self.state = match self.state {
+            State::Magic1 if byte == 0x88 => State::Magic2,
+            State::Magic1 => State::Magic1,
+            State::Magic2 if byte == 0x33 => State::Command,
+            State::Magic2 => State::Magic1,
+            State::Command => {
+                self.command = byte;
+                self.checksum = byte as u16;
+                State::Compression
+            }
+            State::Compression => {
+                self.compressed = byte & 0x01 != 0;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                State::LengthLow
+            }
+            State::LengthLow => {
+                self.length = byte as u16;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                State::LengthHigh
+            }
+            State::LengthHigh => {
+                self.length |= (byte as u16) << 8;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.packet.clear();
+                if self.length == 0 { State::ChecksumLow } else { State::Data }
+            }
+            State::Data => {
+                self.packet.push(byte);
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                if self.packet.len() == self.length as usize { State::ChecksumLow } else { State::Data }
+            }
+            State::ChecksumLow => {
+                self.expected_checksum = byte as u16;
+                State::ChecksumHigh
+            }
+            State::ChecksumHigh => {
+                self.expected_checksum |= (byte as u16) << 8;
+                State::Alive
+            }
+            State::Alive => {
+                response = 0x81;
+                self.run_command();
+                State::Status
+            }
+            State::Status => {
+                response = self.status;
+                State::Magic1
+            }
+        };
+        response
+    }
+
+    fn run_command(&mut self) {
+        if self.checksum != self.expected_checksum {
+            self.status |= CHECKSUM_ERROR;
+            return;
+        }
+        self.status &= !CHECKSUM_ERROR;
+
+        match self.command {
+            INIT => {
+                self.image.clear();
+                self.status = 0;
+                self.busy = 0;
+            }
+            DATA => {
+                let data = if self.compressed { decompress(&self.packet) } else { self.packet.clone() };
+                let space = BUFFER_SIZE - self.image.len();
+                self.image.extend_from_slice(&data[..data.len().min(space)]);
+                self.status |= UNPROCESSED;
+                if self.image.len() == BUFFER_SIZE {
+                    self.status |= IMAGE_FULL;
+                }
+            }
+            PRINT if self.packet.len() >= 4 => {
+                self.print(self.packet[2]);
+                self.image.clear();
+                self.status = (self.status & !(UNPROCESSED | IMAGE_FULL)) | BUSY;
+                self.busy = PRINT_BUSY_POLLS;
+            }
+            STATUS if self.busy > 0 => {
+                self.busy -= 1;
+                if self.busy == 0 {
+                    self.status &= !BUSY;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn print(&mut self, palette: u8) {
+        // Games that don't care about the palette send 0, which the printer treats as the usual one.
+        let (height, rgb) = self.render(if palette == 0 { 0xE4 } else { palette });
+        if height == 0 {
+            return;
+        }
+        self.pages = self.pages.max(self.next_free_page());
+        let path = self.out_dir.join(format!("print_{:04}.png", self.pages));
+        self.pages += 1;
+        if let Err(e) = std::fs::create_dir_all(&self.out_dir).and_then(|_| image::save_png(&path, WIDTH as u32, height as u32, &rgb)) {
+            eprintln!("Unable to save printout to {}: {}", path.display(), e);
+        }
+    }
+
+    // The page after the highest numbered printout already in `out_dir`, so earlier runs' pages
+    // aren't overwritten.
+    fn next_free_page(&self) -> usize {
+        let Ok(entries) = std::fs::read_dir(&self.out_dir) else { return 0 };
+        entries
+            .filter_map(|entry| {
+                let name = entry.ok()?.file_name();
+                name.to_str()?.strip_prefix("print_")?.strip_suffix(".png")?.parse::<usize>().ok()
+            })
+            .map(|page| page + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    // Decodes the buffered tile data into an RGB image 160 pixels wide.
+    fn render(&self, palette: u8) -> (usize, Vec<u8>) {
+        let tile_rows = self.image.len() / (16 * TILES_PER_ROW);
+        let height = tile_rows * 8;
+        let mut rgb = vec![0; WIDTH * height * 3];
+        for (tile, data) in self.image.chunks_exact(16).take(tile_rows * TILES_PER_ROW).enumerate() {
+            let tile_x = (tile % TILES_PER_ROW) * 8;
+            let tile_y = (tile / TILES_PER_ROW) * 8;
+            for row in 0..8 {
+                let (lsb, msb) = (data[row * 2], data[row * 2 + 1]);
+                for col in 0..8 {
+                    let bit = 7 - col;
+                    let colour_num = ((msb >> bit) & 1) << 1 | ((lsb >> bit) & 1);
+                    let shade = match (palette >> (colour_num * 2)) & 0b11 {
+                        0 => 255,
+                        1 => 200,
+                        2 => 100,
+                        _ => 0,
+                    };
+                    let offset = ((tile_y + row) * WIDTH + tile_x + col) * 3;
+                    rgb[offset..offset + 3].copy_from_slice(&[shade; 3]);
+                }
+            }
+        }
+        (height, rgb)
+    }
+}
+
+// Expands the printer's run-length encoding. A control byte with bit 7 set repeats the next byte
+// (n & 0x7F) + 2 times, otherwise the next n + 1 bytes are copied as they are.
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let control = data[i];
+        i += 1;
+        if control & 0x80 != 0 {
+            let count = (control & 0x7F) as usize + 2;
+            if let Some(&byte) = data.get(i) {
+                out.extend(std::iter::repeat_n(byte, count));
+            }
+            i += 1;
+        } else {
+            let count = control as usize + 1;
+            let end = (i + count).min(data.len());
+            out.extend_from_slice(&data[i..end]);
+            i = end;
+        }
+    }
+    out
+}
+
+impl SerialLink for Printer {
+    fn send(&mut self, byte: u8) {
+        self.response = self.exchange(byte);
+    }
+
+    fn recv(&mut self) -> Option<u8> {
+        Some(self.response)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Sends a whole packet and returns the two bytes the printer answers with at the end.
+    fn send_packet(printer: &mut Printer, command: u8, compression: u8, data: &[u8]) -> (u8, u8) {
+        let len = data.len() as u16;
+        let mut bytes = vec![0x88, 0x33, command, compression, len as u8, (len >> 8) as u8];
+        bytes.extend_from_slice(data);
+        let checksum = bytes[2..].iter().fold(0u16, |sum, &b| sum.wrapping_add(b as u16));
+        bytes.extend_from_slice(&[checksum as u8, (checksum >> 8) as u8, 0, 0]);
+        let responses: Vec<u8> = bytes.iter().map(|&b| printer.exchange(b)).collect();
+        (responses[responses.len() - 2], responses[responses.len() - 1])
+    }
+
+    #[test]
+    fn decompress_runs_and_literals() {
+        assert_eq!(decompress(&[0x81, 0xAA, 0x01, 0x12, 0x34]), vec![0xAA, 0xAA, 0xAA, 0x12, 0x34]);
+    }
+
+    #[test]
+    fn data_then_print() {
+        let dir = std::env::temp_dir().join(format!("gebb_printer_{}", std::process::id()));
+        let mut printer = Printer::new(&dir);
+        assert_eq!(send_packet(&mut printer, INIT, 0, &[]), (0x81, 0x00));
+
+        // Two rows of black tiles, compressed into runs.
+        let runs = [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFA, 0xFF];
+        assert_eq!(send_packet(&mut printer, DATA, 1, &runs), (0x81, UNPROCESSED));
+        assert_eq!(printer.image.len(), 0x280);
+        assert_eq!(send_packet(&mut printer, STATUS, 0, &[]).1, UNPROCESSED);
+
+        let mut rows = vec![0xFF; 0x280];
+        rows[0] = 0;
+        send_packet(&mut printer, DATA, 0, &rows);
+        let (height, rgb) = printer.render(0xE4);
+        assert_eq!(height, 32);
+        assert_eq!(rgb[0], 0);
+        assert_eq!(rgb[WIDTH * 3 * 16], 100);
+
+        send_packet(&mut printer, PRINT, 0, &[1, 0x13, 0xE4, 0x40]);
+        assert_eq!(send_packet(&mut printer, STATUS, 0, &[]).1 & BUSY, BUSY);
+        assert!(dir.join("print_0000.png").exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn pages_continue_after_earlier_printouts() {
+        let dir = std::env::temp_dir().join(format!("gebb_printer_pages_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("print_0007.png"), b"earlier").unwrap();
+        let mut printer = Printer::new(&dir);
+        for _ in 0..2 {
+            send_packet(&mut printer, DATA, 0, &[0xFF; 0x280]);
+            send_packet(&mut printer, PRINT, 0, &[1, 0x13, 0x00, 0x40]);
+        }
+        assert_eq!(std::fs::read(dir.join("print_0007.png")).unwrap(), b"earlier");
+        assert!(dir.join("print_0008.png").exists());
+        assert!(dir.join("print_0009.png").exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn zero_palette_prints_like_the_default() {
+        let dir = std::env::temp_dir().join(format!("gebb_printer_palette_{}", std::process::id()));
+        let mut printer = Printer::new(&dir);
+        send_packet(&mut printer, DATA, 0, &[0xFF; 0x280]);
+        send_packet(&mut printer, PRINT, 0, &[1, 0x13, 0x00, 0x40]);
+        let png = std::fs::read(dir.join("print_0000.png")).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let mut printer = Printer::new(&dir);
+        send_packet(&mut printer, DATA, 0, &[0xFF; 0x280]);
+        send_packet(&mut printer, PRINT, 0, &[1, 0x13, 0xE4, 0x40]);
+        assert_eq!(std::fs::read(dir.join("print_0000.png")).unwrap(), png);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn bad_checksum_is_reported() {
+        let mut printer = Printer::new(std::env::temp_dir());
+        for &b in &[0x88, 0x33, STATUS, 0, 0, 0, 0x00, 0x00, 0] {
+            printer.exchange(b);
+        }
+        assert_eq!(printer.exchange(0) & CHECKSUM_ERROR, CHECKSUM_ERROR);
+    }
+}
@@ -0,0 +1,200 @@
+use crate::cpu::Cpu;
+use std::collections::VecDeque;
+
+// Keeps a history of save states so gameplay can be stepped backwards.
+//
+// Only the newest snapshot is stored whole. Every older one is stored as the XOR of itself and
+// the snapshot after it, which is almost entirely zeros between nearby frames and so compresses
+// very well. Stepping back XORs the newest delta into the current snapshot to recover the one
+// before it. The oldest deltas are dropped once the history uses more than `budget` bytes.
+pub struct Rewind {
+    interval: u32,
+    budget: usize,
+    frames: u32,
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+    used: usize,
+}
+
+impl Rewind {
+    // Snapshots every `interval` frames, keeping at most about `budget` bytes of history.
+    pub fn new(interval: u32, budget: usize) -> Self {
+        Self {
+            interval: interval.max(1),
+            budget,
+            frames: 0,
+            latest: None,
+            deltas: VecDeque::new(),
+            used: 0,
+        }
+    }
+
+    // Call once per emulated frame.
+    pub fn push_frame(&mut self, cpu: &Cpu) {
+        self.frames += 1;
+        if self.frames < self.interval {
+            return;
+        }
+        self.frames = 0;
+
+        let state = cpu.save_state();
+        if let Some(previous) = self.latest.take() {
+            let delta = compress(&xor(&previous, &state));
+            self.used += delta.len();
+            self.deltas.push_back(delta);
+        }
+        self.latest = Some(state);
+
+        while self.memory_used() > self.budget {
+            match self.deltas.pop_front() {
+                Some(delta) => self.used -= delta.len(),
+                None => break,
+            }
+        }
+    }
+
+    // Restores the snapshot before the newest one. Returns false once the history is used up.
+    pub fn step_back(&mut self, cpu: &mut Cpu) -> bool {
+        let (Some(latest), Some(delta)) = (self.latest.as_mut(), self.deltas.pop_back()) else {
+            return false;
+        };
+        self.used -= delta.len();
+        decompress_xor(&delta, latest);
+        self.frames = 0;
+        cpu.load_state(latest).is_ok()
+    }
+
+    // Number of snapshots that can still be stepped back to.
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+        self.used = 0;
+        self.frames = 0;
+    }
+
+    pub fn memory_used(&self) -> usize {
+        self.used + self.latest.as_ref().map_or(0, |s| s.len())
+    }
+}
+
+fn xor(a: &[u8], b: &[u8]) -> Vec<u8> {
+    a.iter().zip(b).map(|(x, y)| x ^ y).collect()
+}
+
+// Encodes the data as pairs of (zero run length, literal length) varints, each followed by the
+// literal bytes.
+fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let zeros = data[i..].iter().take_while(|&&b| b == 0).count();
+        i += zeros;
+        let start = i;
+        // A literal ends at the next run of at least 4 zeros, shorter ones cost less to copy.
+        while i < data.len() {
+            let run = data[i..].iter().take(4).take_while(|&&b| b == 0).count();
+            if run == 4 || i + run == data.len() {
+                break;
+            }
+            i += 1;
+        }
+        write_varint(&mut out, zeros);
+        write_varint(&mut out, i - start);
+        out.extend_from_slice(&data[start..i]);
+    }
+    out
+}
+
+// XORs compressed data into `target` in place.
+fn decompress_xor(data: &[u8], target: &mut [u8]) {
+    let mut pos = 0;
+    let mut i = 0;
+    while i < data.len() {
+        pos += read_varint(data, &mut i);
+        let literal = read_varint(data, &mut i);
+        for (t, d) in target[pos..pos + literal].iter_mut().zip(&data[i..i + literal]) {
+            *t ^= d;
+        }
+        pos += literal;
+        i += literal;
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut v: usize) {
+    while v >= 0x80 {
+        out.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+fn read_varint(data: &[u8], i: &mut usize) -> usize {
+    let mut v = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*i];
+        *i += 1;
+        v |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return v;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn compress_round_trip() {
+        let mut data = vec![0; 1000];
+        data[3] = 1;
+        data[4] = 2;
+        data[6] = 3;
+        data[500] = 4;
+        data[999] = 5;
+        let packed = compress(&data);
+        assert!(packed.len() < 20);
+
+        let mut out = vec![0; 1000];
+        decompress_xor(&packed, &mut out);
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn steps_back_through_snapshots() {
+        let mut cpu = Cpu::new();
+        let mut rewind = Rewind::new(1, usize::MAX);
+        for v in 0..5 {
+            cpu.mmu.write_byte(0xC000, v);
+            rewind.push_frame(&cpu);
+        }
+        assert_eq!(rewind.len(), 4);
+
+        assert!(rewind.step_back(&mut cpu));
+        assert_eq!(cpu.mmu.read_byte(0xC000), 3);
+        assert!(rewind.step_back(&mut cpu));
+        assert_eq!(cpu.mmu.read_byte(0xC000), 2);
+    }
+
+    #[test]
+    fn budget_drops_oldest() {
+        let mut cpu = Cpu::new();
+        let mut rewind = Rewind::new(1, 0);
+        for v in 0..3 {
+            cpu.mmu.write_byte(0xC000, v);
+            rewind.push_frame(&cpu);
+        }
+        assert!(rewind.is_empty());
+        assert!(!rewind.step_back(&mut cpu));
+    }
+}
@@ -0,0 +1,248 @@
+use crate::state::{StateError, StateReader, StateWriter};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+// One bit is shifted every 512 ticks with the internal 8192 Hz clock, so a full byte takes 4096.
+pub(crate) const TICKS_PER_BYTE: u32 = 512 * 8;
+
+// Whatever is plugged into the other end of the link port.
+pub trait SerialLink: Send {
+    // Called with the ticks run by every instruction, before anything else the port does then.
+    // Links to other emulators use it to keep in step with them.
+    fn tick(&mut self, _ticks: u32) {}
+
+    // Called when this side starts a transfer using its own (internal) clock.
+    fn send(&mut self, byte: u8);
+
+    // Called once all 8 bits have been clocked out. Returns the byte shifted in from the other
+    // side, or None if it isn't available yet, in which case the transfer stays pending.
+    fn recv(&mut self) -> Option<u8>;
+
+    // Polled while this side waits on an external clock. Returns the byte clocked in by the other
+    // side, which in exchange receives `byte`.
+    fn external(&mut self, _byte: u8) -> Option<u8> {
+        None
+    }
+}
+
+// No cable connected. Every transfer shifts in 0xFF and external clocks never arrive.
+pub struct NoCable;
+
+impl SerialLink for NoCable {
+    fn send(&mut self, _byte: u8) {}
+
+    fn recv(&mut self) -> Option<u8> {
+        Some(0xFF)
+    }
+}
+
+// Collects every byte sent, optionally echoing it to stdout. Test ROMs (Blargg's for example)
+// report their results this way.
+pub struct StdoutLink {
+    output: Arc<Mutex<Vec<u8>>>,
+    echo: bool,
+}
+
+impl StdoutLink {
+    pub fn new(echo: bool) -> Self {
+        Self {
+            output: Arc::new(Mutex::new(Vec::new())),
+            echo,
+        }
+    }
+
+    // Shared handle to the captured bytes, which stays valid once the link is given to the Serial.
+    pub fn output(&self) -> Arc<Mutex<Vec<u8>>> {
+        self.output.clone()
+    }
+}
+
+impl SerialLink for StdoutLink {
+    fn send(&mut self, byte: u8) {
+        self.output.lock().unwrap().push(byte);
+        if self.echo {
+            let mut stdout = std::io::stdout();
+            let _ = stdout.write_all(&[byte]);
+            let _ = stdout.flush();
+        }
+    }
+
+    fn recv(&mut self) -> Option<u8> {
+        Some(0xFF)
+    }
+}
+
+// Cable plugged back into the same Game Boy, so every byte sent is received again.
+pub struct Loopback {
+    byte: u8,
+}
+
+impl Loopback {
+    pub fn new() -> Self {
+        Self { byte: 0xFF }
+    }
+}
+
+impl Default for Loopback {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SerialLink for Loopback {
+    fn send(&mut self, byte: u8) {
+        self.byte = byte;
+    }
+
+    fn recv(&mut self) -> Option<u8> {
+        Some(self.byte)
+    }
+}
+
+pub struct Serial {
+    data: u8,    // SB
+    control: u8, // SC
+    counter: u32,
+    link: Box<dyn SerialLink>,
+    pub interrupt: u8,
+}
+
+impl Serial {
+    pub fn new() -> Serial {
+        Serial {
+            data: 0,
+            control: 0,
+            counter: 0,
+            link: Box::new(NoCable),
+            interrupt: 0,
+        }
+    }
+
+    // Replaces whatever is connected to the link port.
+    pub fn set_link(&mut self, link: Box<dyn SerialLink>) {
+        self.link = link;
+    }
+
+    // The link itself isn't saved, only the state of the port.
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.data);
+        w.u8(self.control);
+        w.u32(self.counter);
+        w.u8(self.interrupt);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.data = r.u8()?;
+        self.control = r.u8()?;
+        self.counter = r.u32()?;
+        self.interrupt = r.u8()?;
+        Ok(())
+    }
+
+    // Other addresses read as 0xFF and ignore writes, like unmapped memory.
+    pub fn rb(&self, a: u16) -> u8 {
+        match a {
+            0xFF01 => self.data,
+            0xFF02 => self.control | 0x7E,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn wb(&mut self, a: u16, v: u8) {
+        match a {
+            0xFF01 => self.data = v,
+            0xFF02 => {
+                self.control = v & 0x81;
+                if v & 0x81 == 0x81 {
+                    self.link.send(self.data);
+                    self.counter = TICKS_PER_BYTE;
+                }
+            }
+            _ => {}
+        };
+    }
+
+    pub fn do_cycle(&mut self, ticks: u32) {
+        self.link.tick(ticks);
+        if self.control & 0x80 == 0 {
+            return;
+        }
+
+        let received = if self.control & 0x01 != 0 {
+            if self.counter > ticks {
+                self.counter -= ticks;
+                return;
+            }
+            self.counter = 0;
+            self.link.recv()
+        } else {
+            self.link.external(self.data)
+        };
+
+        if let Some(byte) = received {
+            self.data = byte;
+            self.control &= 0x7F;
+            self.interrupt |= 0x08;
+        }
+    }
+}
+
+impl Default for Serial {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn internal_transfer_takes_a_byte_period() {
+        let mut serial = Serial::new();
+        serial.wb(0xFF01, 0x42);
+        serial.wb(0xFF02, 0x81);
+
+        serial.do_cycle(TICKS_PER_BYTE - 4);
+        assert_eq!(serial.interrupt, 0);
+        assert_eq!(serial.rb(0xFF02) & 0x80, 0x80);
+
+        serial.do_cycle(4);
+        assert_eq!(serial.interrupt, 0x08);
+        assert_eq!(serial.rb(0xFF02) & 0x80, 0);
+        assert_eq!(serial.rb(0xFF01), 0xFF);
+    }
+
+    #[test]
+    fn loopback_returns_sent_byte() {
+        let mut serial = Serial::new();
+        serial.set_link(Box::new(Loopback::new()));
+        serial.wb(0xFF01, 0x42);
+        serial.wb(0xFF02, 0x81);
+        serial.do_cycle(TICKS_PER_BYTE);
+        assert_eq!(serial.rb(0xFF01), 0x42);
+    }
+
+    #[test]
+    fn external_clock_waits_without_cable() {
+        let mut serial = Serial::new();
+        serial.wb(0xFF02, 0x80);
+        serial.do_cycle(TICKS_PER_BYTE * 4);
+        assert_eq!(serial.interrupt, 0);
+        assert_eq!(serial.rb(0xFF02) & 0x80, 0x80);
+    }
+
+    #[test]
+    fn stdout_link_captures_output() {
+        let link = StdoutLink::new(false);
+        let output = link.output();
+        let mut serial = Serial::new();
+        serial.set_link(Box::new(link));
+        for &b in b"ok" {
+            serial.wb(0xFF01, b);
+            serial.wb(0xFF02, 0x81);
+            serial.do_cycle(TICKS_PER_BYTE);
+        }
+        assert_eq!(&*output.lock().unwrap(), b"ok");
+    }
+}
@@ -0,0 +1,178 @@
+use std::fmt;
+
+// Save states start with this magic, the format version and the checksum of the ROM they were made
+// with. Bump VERSION whenever the layout of any component's state changes.
+pub const MAGIC: &[u8; 4] = b"GEBB";
+pub const VERSION: u32 = 4;
+
+#[derive(Debug, PartialEq)]
+pub enum StateError {
+    BadMagic,
+    UnsupportedVersion(u32),
+    WrongRom { expected: u32, found: u32 },
+    WrongSize,
+    Truncated,
+    // A value no real machine could be in, such as an impossible timer rate.
+    Invalid,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(v) => write!(f, "unsupported save state version {}", v),
+            StateError::WrongRom { expected, found } => {
+                write!(f, "save state is for ROM {:08X}, but {:08X} is loaded", found, expected)
+            }
+            StateError::WrongSize => write!(f, "save state has the wrong size"),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Invalid => write!(f, "save state is corrupt"),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+// Appends little-endian values to a save state.
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Self { data: Vec::new() }
+    }
+
+    pub fn u8(&mut self, v: u8) {
+        self.data.push(v);
+    }
+
+    pub fn bool(&mut self, v: bool) {
+        self.data.push(v as u8);
+    }
+
+    pub fn u16(&mut self, v: u16) {
+        self.data.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, v: u32) {
+        self.data.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, v: u64) {
+        self.data.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, v: &[u8]) {
+        self.data.extend_from_slice(v);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+impl Default for StateWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Reads values back in the order a StateWriter wrote them.
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < n {
+            return Err(StateError::Truncated);
+        }
+        let (head, tail) = self.data.split_at(n);
+        self.data = tail;
+        Ok(head)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    // Fills `out` with the next out.len() bytes.
+    pub fn bytes(&mut self, out: &mut [u8]) -> Result<(), StateError> {
+        out.copy_from_slice(self.take(out.len())?);
+        Ok(())
+    }
+
+    // Reads a u32 length and then that many bytes. The length is checked against what's left
+    // before anything is allocated, so a corrupt length can't ask for gigabytes.
+    pub fn sized_bytes(&mut self) -> Result<Vec<u8>, StateError> {
+        let len = self.u32()? as usize;
+        if len > self.data.len() {
+            return Err(StateError::WrongSize);
+        }
+        Ok(self.take(len)?.to_vec())
+    }
+}
+
+// CRC-32 (IEEE), used to tell which ROM a save state belongs to.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut w = StateWriter::new();
+        w.u8(1);
+        w.u16(0x1234);
+        w.u32(0xDEADBEEF);
+        w.bool(true);
+        w.bytes(&[9, 8, 7]);
+        let data = w.finish();
+
+        let mut r = StateReader::new(&data);
+        assert_eq!(r.u8(), Ok(1));
+        assert_eq!(r.u16(), Ok(0x1234));
+        assert_eq!(r.u32(), Ok(0xDEADBEEF));
+        assert_eq!(r.bool(), Ok(true));
+        let mut out = [0; 3];
+        r.bytes(&mut out).unwrap();
+        assert_eq!(out, [9, 8, 7]);
+        assert_eq!(r.u8(), Err(StateError::Truncated));
+    }
+}
@@ -0,0 +1,174 @@
+use crate::cpu::mmu::MMU;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SymbolError {
+    // 1-based line number of the line that couldn't be parsed.
+    pub line: usize,
+}
+
+impl fmt::Display for SymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid symbol on line {}", self.line)
+    }
+}
+
+impl std::error::Error for SymbolError {}
+
+// Labels loaded from a symbol file in the RGBDS or no$gmb format, where each line is
+//
+//     01:4A2F Main.loop
+//
+// giving the bank and address in hex. Anything after a semicolon is a comment.
+#[derive(Clone, Debug, Default)]
+pub struct Symbols {
+    // Only the first label at each address is kept here, which is usually the global one.
+    by_addr: BTreeMap<(u16, u16), String>,
+    by_name: HashMap<String, (u16, u16)>,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn parse(text: &str) -> Result<Self, SymbolError> {
+        let mut symbols = Self::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = SymbolError { line: i + 1 };
+            let (location, name) = line.split_once(char::is_whitespace).ok_or(error.clone())?;
+            let (bank, addr) = location.split_once(':').ok_or(error.clone())?;
+            let bank = u16::from_str_radix(bank, 16).map_err(|_| error.clone())?;
+            let addr = u16::from_str_radix(addr, 16).map_err(|_| error)?;
+            symbols.insert(bank, addr, name.trim());
+        }
+        Ok(symbols)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        Self::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    // Loads the symbol file RGBDS would write next to `rom`, if there is one.
+    pub fn load_for_rom(rom: &Path) -> io::Result<Option<Self>> {
+        let path = rom.with_extension("sym");
+        if !path.exists() {
+            return Ok(None);
+        }
+        Self::load(&path).map(Some)
+    }
+
+    pub fn insert(&mut self, bank: u16, addr: u16, name: &str) {
+        self.by_addr.entry((bank, addr)).or_insert_with(|| name.to_string());
+        self.by_name.insert(name.to_string(), (bank, addr));
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_name.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+
+    // The label at `addr` in `bank`.
+    pub fn get(&self, bank: u16, addr: u16) -> Option<&str> {
+        self.by_addr.get(&(bank, addr)).map(String::as_str)
+    }
+
+    // The bank and address of a label.
+    pub fn address(&self, name: &str) -> Option<(u16, u16)> {
+        self.by_name.get(name).copied()
+    }
+
+    // The label at `addr` as currently mapped by `mmu`. Symbols in bank 0 are also tried, since
+    // RGBDS puts everything there for ROMs without banking.
+    pub fn label(&self, mmu: &MMU, addr: u16) -> Option<&str> {
+        self.get(mmu.bank_at(addr), addr).or_else(|| self.get(0, addr))
+    }
+
+    // Describes `addr` in `bank` as the closest label before it plus an offset, like Main+$1A.
+    // Only labels in the same bank and memory region are considered.
+    pub fn locate(&self, bank: u16, addr: u16) -> Option<String> {
+        [bank, 0].iter().find_map(|&bank| {
+            let ((_, start), name) = self.by_addr.range((bank, region(addr))..=(bank, addr)).next_back()?;
+            Some(match addr - start {
+                0 => name.clone(),
+                offset => format!("{}+${:X}", name, offset),
+            })
+        })
+    }
+
+    // locate, using the bank currently mapped at `addr`.
+    pub fn locate_live(&self, mmu: &MMU, addr: u16) -> Option<String> {
+        self.locate(mmu.bank_at(addr), addr)
+    }
+}
+
+// Start of the memory region `addr` is in, so labels don't run on from one region into the next.
+fn region(addr: u16) -> u16 {
+    match addr {
+        0x0000..=0x3FFF => 0x0000,
+        0x4000..=0x7FFF => 0x4000,
+        0x8000..=0x9FFF => 0x8000,
+        0xA000..=0xBFFF => 0xA000,
+        0xC000..=0xCFFF => 0xC000,
+        0xD000..=0xDFFF => 0xD000,
+        0xE000..=0xFDFF => 0xE000,
+        0xFE00..=0xFEFF => 0xFE00,
+        0xFF00..=0xFF7F => 0xFF00,
+        _ => 0xFF80,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SYM: &str = "; File generated by rgblink
+00:0150 Start
+00:0150 Start.init
+00:0200 Main
+01:4000 LevelData
+02:4000 Music ; comment
+00:C000 wBuffer
+";
+
+    #[test]
+    fn parses_rgbds_symbols() {
+        let symbols = Symbols::parse(SYM).unwrap();
+        assert_eq!(symbols.len(), 6);
+        assert_eq!(symbols.get(0, 0x0150), Some("Start"));
+        assert_eq!(symbols.get(2, 0x4000), Some("Music"));
+        assert_eq!(symbols.address("Start.init"), Some((0, 0x0150)));
+        assert_eq!(symbols.address("Music"), Some((2, 0x4000)));
+
+        assert_eq!(symbols.locate(0, 0x0210).as_deref(), Some("Main+$10"));
+        assert_eq!(symbols.locate(1, 0x4003).as_deref(), Some("LevelData+$3"));
+        assert_eq!(symbols.locate(0, 0x0100), None);
+        assert_eq!(symbols.locate(0, 0x4000), None);
+        assert_eq!(symbols.locate(0, 0xC0FF).as_deref(), Some("wBuffer+$FF"));
+
+        assert_eq!(Symbols::parse("00:0150 Start\nnonsense\n").unwrap_err(), SymbolError { line: 2 });
+    }
+
+    #[test]
+    fn labels_follow_the_mapped_bank() {
+        let symbols = Symbols::parse(SYM).unwrap();
+        let mut mmu = MMU::new();
+        assert_eq!(symbols.label(&mmu, 0x4000), Some("LevelData"));
+        assert_eq!(symbols.label(&mmu, 0x0200), Some("Main"));
+        mmu.write_byte(0x2000, 2);
+        assert_eq!(symbols.label(&mmu, 0x4000), Some("Music"));
+        assert_eq!(symbols.label(&mmu, 0xC000), Some("wBuffer"));
+    }
+}
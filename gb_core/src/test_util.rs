@@ -0,0 +1,29 @@
+// Fixtures shared by the tests of this crate and the crates built on it, which get them through
+// the test-util feature.
+
+use crate::cpu::Cpu;
+use std::thread::{self, JoinHandle};
+
+// A Cpu is a few hundred KiB and debug builds copy it around, so tests running more than one need
+// more than the default test thread stack.
+const STACK_SIZE: usize = 64 << 20;
+
+// CALL $0150; LD ($C000),A; JR -2 at $0100, and INC A; RET at $0150.
+pub fn test_cpu() -> Cpu {
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x108].copy_from_slice(&[0xCD, 0x50, 0x01, 0xEA, 0x00, 0xC0, 0x18, 0xFE]);
+    rom[0x150..0x152].copy_from_slice(&[0x3C, 0xC9]);
+    let mut cpu = Cpu::new();
+    cpu.load(&rom).unwrap();
+    cpu
+}
+
+// Starts `f` on a thread with a stack big enough for a few Cpus.
+pub fn spawn_with_stack<F: FnOnce() -> T + Send + 'static, T: Send + 'static>(f: F) -> JoinHandle<T> {
+    thread::Builder::new().stack_size(STACK_SIZE).spawn(f).unwrap()
+}
+
+// Runs `f` on a thread with a stack big enough for a few Cpus, passing on any panic.
+pub fn with_stack<F: FnOnce() -> T + Send + 'static, T: Send + 'static>(f: F) -> T {
+    spawn_with_stack(f).join().unwrap()
+}
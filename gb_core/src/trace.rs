@@ -0,0 +1,207 @@
+use crate::cpu::registers::Registers;
+use crate::symbols::Symbols;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+// Where a trace first stopped matching its reference log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    // 1-based line number in the reference log.
+    pub line: u64,
+    // None if the reference log ended first.
+    pub expected: Option<String>,
+    pub actual: String,
+    // The last line that matched, which is usually the instruction that went wrong.
+    pub previous: Option<String>,
+    // Where the diverging instruction is, like Main+$1A, if the tracer has symbols.
+    pub location: Option<String>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Trace diverged from the reference log at line {}", self.line)?;
+        if let Some(location) = &self.location {
+            writeln!(f, "  in:         {}", location)?;
+        }
+        if let Some(previous) = &self.previous {
+            writeln!(f, "  last match: {}", previous)?;
+        }
+        match &self.expected {
+            Some(expected) => {
+                writeln!(f, "  expected:   {}", expected)?;
+                writeln!(f, "  actual:     {}", self.actual)?;
+                // Both lines are NAME:VALUE fields, so point out which ones differ.
+                let differing: Vec<&str> = expected
+                    .split_whitespace()
+                    .zip(self.actual.split_whitespace())
+                    .filter(|(e, a)| e != a)
+                    .map(|(e, _)| e.split(':').next().unwrap_or(e))
+                    .collect();
+                write!(f, "  differs in: {}", differing.join(", "))
+            }
+            None => {
+                writeln!(f, "  expected:   end of log")?;
+                write!(f, "  actual:     {}", self.actual)
+            }
+        }
+    }
+}
+
+// Logs the CPU state before every instruction in the Gameboy Doctor format:
+//
+//     A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02
+//
+// and optionally compares each line against a reference log made by another emulator. Once the
+// trace diverges it stops logging and comparing, and the Divergence is kept for the frontend to
+// report.
+pub struct Tracer {
+    log: Option<Box<dyn Write>>,
+    reference: Option<Box<dyn BufRead>>,
+    lines: u64,
+    previous: Option<String>,
+    divergence: Option<Divergence>,
+    error: Option<io::Error>,
+    symbols: Option<Symbols>,
+}
+
+impl Tracer {
+    pub fn new(log: Option<Box<dyn Write>>, reference: Option<Box<dyn BufRead>>) -> Self {
+        Self {
+            log,
+            reference,
+            lines: 0,
+            previous: None,
+            divergence: None,
+            error: None,
+            symbols: None,
+        }
+    }
+
+    // Writes the trace to `log` and/or compares it against `reference`, either of which may be
+    // left out.
+    pub fn open(log: Option<&Path>, reference: Option<&Path>) -> io::Result<Self> {
+        let log = match log {
+            Some(path) => Some(Box::new(BufWriter::new(File::create(path)?)) as Box<dyn Write>),
+            None => None,
+        };
+        let reference = match reference {
+            Some(path) => Some(Box::new(BufReader::new(File::open(path)?)) as Box<dyn BufRead>),
+            None => None,
+        };
+        Ok(Self::new(log, reference))
+    }
+
+    // Lets divergences be reported by label. The log itself stays in the plain Gameboy Doctor
+    // format so it can still be compared against other emulators.
+    pub fn set_symbols(&mut self, symbols: Option<Symbols>) {
+        self.symbols = symbols;
+    }
+
+    // Number of instructions traced.
+    pub fn lines(&self) -> u64 {
+        self.lines
+    }
+
+    pub fn divergence(&self) -> Option<&Divergence> {
+        self.divergence.as_ref()
+    }
+
+    // The first error writing the log or reading the reference, after which tracing stops.
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self.log.as_mut() {
+            Some(log) => log.flush(),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn instruction(&mut self, r: &Registers, sp: u16, pc: u16, pcmem: [u8; 4], bank: u16) {
+        if self.divergence.is_some() || self.error.is_some() {
+            return;
+        }
+        let line = format!(
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            r.a, r.f, r.b, r.c, r.d, r.e, r.h, r.l, sp, pc, pcmem[0], pcmem[1], pcmem[2], pcmem[3]
+        );
+        self.lines += 1;
+        if let Err(e) = self.check(&line, bank, pc) {
+            self.error = Some(e);
+            return;
+        }
+        if let Some(log) = self.log.as_mut() {
+            if let Err(e) = writeln!(log, "{}", line) {
+                self.error = Some(e);
+            }
+        }
+        self.previous = Some(line);
+    }
+
+    fn check(&mut self, line: &str, bank: u16, pc: u16) -> io::Result<()> {
+        let Some(reference) = self.reference.as_mut() else { return Ok(()) };
+        let mut expected = String::new();
+        let expected = match reference.read_line(&mut expected)? {
+            0 => None,
+            _ => Some(expected.trim_end().to_string()),
+        };
+        if expected.as_deref() != Some(line) {
+            self.divergence = Some(Divergence {
+                line: self.lines,
+                expected,
+                actual: line.to_string(),
+                previous: self.previous.clone(),
+                location: self.symbols.as_ref().and_then(|s| s.locate(bank, pc)),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    fn registers(a: u8) -> Registers {
+        let mut r = Registers::new_default();
+        r.a = a;
+        r
+    }
+
+    #[test]
+    fn gameboy_doctor_format() {
+        let mut tracer = Tracer::new(None, None);
+        tracer.instruction(&Registers::new_default(), 0xFFFE, 0x0100, [0x00, 0xC3, 0x13, 0x02], 0);
+        assert_eq!(
+            tracer.previous.as_deref(),
+            Some("A:11 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02")
+        );
+    }
+
+    #[test]
+    fn reports_first_divergence() {
+        let mut reference = Vec::new();
+        for a in [1, 2, 3] {
+            let mut tracer = Tracer::new(None, None);
+            tracer.instruction(&registers(a), 0xFFFE, 0x0100, [0; 4], 0);
+            reference.extend_from_slice(tracer.previous.unwrap().as_bytes());
+            reference.push(b'\n');
+        }
+
+        let mut tracer = Tracer::new(None, Some(Box::new(Cursor::new(reference))));
+        tracer.set_symbols(Some(Symbols::parse("00:00F0 Boot\n").unwrap()));
+        for a in [1, 2, 4, 5] {
+            tracer.instruction(&registers(a), 0xFFFE, 0x0100, [0; 4], 0);
+        }
+        let divergence = tracer.divergence().unwrap();
+        assert_eq!(divergence.line, 3);
+        assert_eq!(divergence.location.as_deref(), Some("Boot+$10"));
+        assert!(divergence.previous.as_ref().unwrap().starts_with("A:02"));
+        assert!(divergence.to_string().ends_with("differs in: A"));
+        assert_eq!(tracer.lines(), 3);
+    }
+}
@@ -0,0 +1,157 @@
+// Compares the screen against reference images after running PPU test ROMs.
+//
+// `pattern` runs a ROM built here, which draws scrolled background tiles and sprites through
+// palettes that move every colour to a different shade, against tests/screenshots/pattern.png. The
+// reference was drawn from the ROM's tile, map and OAM data rather than captured from the emulator.
+//
+// `screenshots` runs external ROMs such as dmg-acid2. Every NAME.gb in the directory named by GEBB_SCREENSHOT_ROMS that has a NAME.png next to it is
+// run for GEBB_SCREENSHOT_FRAMES frames (120 by default), and the final frame must match the
+// reference pixel for pixel. References may use the common 0x00/0x55/0xAA/0xFF greys or the
+// emulator's own palette, and each pixel must be exactly the same shade. Any other colour, in the
+// output or the reference, counts as a mismatch. On a mismatch, NAME.diff.png is written to the
+// target tmp directory with wrong pixels in red over a faded copy of the output.
+//
+// That test is skipped when GEBB_SCREENSHOT_ROMS isn't set.
+
+use gb_core::cpu::Cpu;
+use gb_core::image;
+use gb_core::ppu::SHADES;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const WIDTH: u32 = 160;
+const HEIGHT: u32 = 144;
+
+// Greys commonly used for reference images, lightest first.
+const REFERENCE_SHADES: [(u8, u8, u8); 4] =
+    [(0xFF, 0xFF, 0xFF), (0xAA, 0xAA, 0xAA), (0x55, 0x55, 0x55), (0x00, 0x00, 0x00)];
+
+// The shade an emulator colour is, or None if it isn't exactly one of them.
+fn actual_shade(rgb: &[u8]) -> Option<usize> {
+    SHADES.iter().position(|&(r, g, b)| rgb == [r, g, b])
+}
+
+// The shade a reference colour is, exactly matching either the common greys or the emulator's own
+// palette, or None for anything else.
+fn expected_shade(rgb: &[u8]) -> Option<usize> {
+    REFERENCE_SHADES.iter().position(|&(r, g, b)| rgb == [r, g, b]).or_else(|| actual_shade(rgb))
+}
+
+// Returns the number of mismatched pixels, writing a diff image if there are any.
+fn compare(actual: &[u8], expected: &[u8], diff_path: &Path) -> usize {
+    let mut diff = Vec::with_capacity(actual.len());
+    let mut mismatched = 0;
+    for (a, e) in actual.chunks_exact(3).zip(expected.chunks_exact(3)) {
+        if actual_shade(a).is_some() && actual_shade(a) == expected_shade(e) {
+            diff.extend(a.iter().map(|&c| 128 + c / 2));
+        } else {
+            mismatched += 1;
+            diff.extend_from_slice(&[255, 0, 0]);
+        }
+    }
+    if mismatched > 0 {
+        image::save_png(diff_path, WIDTH, HEIGHT, &diff).unwrap();
+    }
+    mismatched
+}
+
+fn run(rom: &[u8], frames: u32) -> Vec<u8> {
+    let mut gb = Cpu::new();
+    gb.load(rom).unwrap();
+    for _ in 0..frames {
+        gb.run_frame().unwrap();
+    }
+    gb.get_display().to_vec()
+}
+
+// Runs `rom` and compares the final frame with `reference`, describing any failure.
+fn check(name: &str, rom: &[u8], reference: &Path, frames: u32) -> Result<(), String> {
+    let (width, height, expected) = image::load_png(reference).unwrap();
+    if (width, height) != (WIDTH, HEIGHT) {
+        return Err(format!("{}: reference is {}x{}", name, width, height));
+    }
+
+    let diff_path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.diff.png", name));
+    let mismatched = compare(&run(rom, frames), &expected, &diff_path);
+    if mismatched > 0 {
+        return Err(format!("{}: {} pixels differ, see {}", name, mismatched, diff_path.display()));
+    }
+    Ok(())
+}
+
+// Tiles 0-3 are shade N on top with N+1 and N+2 below, and 4 and 5 are for sprites.
+const PATTERN_TILES: [u8; 96] = [
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xF0, 0x0F, 0xF0, 0x0F, 0xF0, 0x0F, 0xF0, 0x0F,
+    0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0x0F, 0xFF, 0x0F, 0xFF, 0x0F, 0xFF, 0x0F, 0xFF,
+    0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0xF0, 0xF0, 0xF0, 0xF0, 0xF0, 0xF0, 0xF0, 0xF0,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x0F, 0x00, 0x0F, 0x00, 0x0F, 0x00, 0x0F, 0x00,
+    0xF0, 0x3C, 0xF0, 0x3C, 0xF0, 0x3C, 0xF0, 0x3C, 0x0F, 0x00, 0x0F, 0x00, 0x0F, 0x00, 0x0F, 0x00,
+    0x80, 0x81, 0x40, 0x42, 0x20, 0x24, 0x10, 0x18, 0x08, 0x18, 0x04, 0x24, 0x02, 0x42, 0x01, 0x81,
+];
+
+// Y, X, tile and attributes: both palettes, a vertical flip and sprites cut off by each edge.
+const PATTERN_OAM: [u8; 28] = [
+    36, 28, 4, 0x00, 36, 48, 4, 0x10, 36, 68, 4, 0x40, 80, 100, 5, 0x10, 84, 120, 5, 0x00, 10, 4, 5,
+    0x00, 60, 164, 4, 0x00,
+];
+
+// Waits for VBlank, turns the LCD off, copies in the tiles and OAM, fills the map at $9800 with
+// tile (column + row) & 3, then sets BGP $D2, OBP0 $E4, OBP1 $9C, SCY 5 and SCX 3 and turns the
+// LCD back on.
+fn pattern_rom() -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+    let code = [
+        0xF0, 0x44, 0xFE, 0x90, 0x20, 0xFA, // wait: LDH A,($44); CP 144; JR NZ,wait
+        0xAF, 0xE0, 0x40, // XOR A; LDH ($40),A
+        0x21, 0x00, 0x80, 0x11, 0x00, 0x02, 0x0E, 0x60, // LD HL,$8000; LD DE,$0200; LD C,96
+        0x1A, 0x22, 0x13, 0x0D, 0x20, 0xFA, // tiles: LD A,(DE); LD (HL+),A; INC DE; DEC C; JR NZ,tiles
+        0x21, 0x00, 0x98, // LD HL,$9800
+        0x7D, 0xCB, 0x37, 0x0F, 0x85, 0xE6, 0x03, // map: LD A,L; SWAP A; RRCA; ADD A,L; AND 3
+        0x22, 0x7C, 0xFE, 0x9C, 0x20, 0xF3, // LD (HL+),A; LD A,H; CP $9C; JR NZ,map
+        0x21, 0x00, 0xFE, 0x11, 0x80, 0x02, 0x0E, 0xA0, // LD HL,$FE00; LD DE,$0280; LD C,160
+        0x1A, 0x22, 0x13, 0x0D, 0x20, 0xFA, // oam: LD A,(DE); LD (HL+),A; INC DE; DEC C; JR NZ,oam
+        0x3E, 0xD2, 0xE0, 0x47, 0x3E, 0xE4, 0xE0, 0x48, 0x3E, 0x9C, 0xE0, 0x49, // palettes
+        0x3E, 0x05, 0xE0, 0x42, 0x3E, 0x03, 0xE0, 0x43, // SCY, SCX
+        0x3E, 0x93, 0xE0, 0x40, // LCD, BG and sprites on, tiles at $8000, map at $9800
+        0x18, 0xFE, // JR -2
+    ];
+    rom[0x150..0x150 + code.len()].copy_from_slice(&code);
+    rom[0x200..0x260].copy_from_slice(&PATTERN_TILES);
+    rom[0x280..0x29C].copy_from_slice(&PATTERN_OAM);
+    rom
+}
+
+#[test]
+fn pattern() {
+    let reference = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/screenshots/pattern.png");
+    check("pattern", &pattern_rom(), &reference, 10).unwrap();
+}
+
+#[test]
+fn screenshots() {
+    let Ok(dir) = env::var("GEBB_SCREENSHOT_ROMS") else {
+        eprintln!("GEBB_SCREENSHOT_ROMS is not set, skipping screenshot tests");
+        return;
+    };
+    let frames = env::var("GEBB_SCREENSHOT_FRAMES").ok().and_then(|f| f.parse().ok()).unwrap_or(120);
+    let mut roms: Vec<PathBuf> = fs::read_dir(&dir)
+        .unwrap()
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|e| e == "gb") && p.with_extension("png").exists())
+        .collect();
+    roms.sort();
+    assert!(!roms.is_empty(), "No ROMs with reference images found in {}", dir);
+
+    let mut failures = Vec::new();
+    for rom in &roms {
+        let name = rom.file_stem().unwrap().to_string_lossy().into_owned();
+        if let Err(e) = check(&name, &fs::read(rom).unwrap(), &rom.with_extension("png"), frames) {
+            failures.push(e);
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}\n", failures.join("\n"));
+}
@@ -0,0 +1,213 @@
+// Runs the per-opcode SM83 single-step test vectors (one JSON file per opcode, such as "00.json"
+// or "cb 7e.json") from the directory named by GEBB_SM83_TESTS.
+//
+// Each test gives the registers and memory before and after one instruction, plus the bus
+// activity of every machine cycle. Instructions run against a flat 64 KiB memory, and the final
+// registers, IME, memory contents and cycle count must all match. So must the reads and writes:
+// the same addresses and values, in the same order as the cycles that access memory.
+//
+// GEBB_SM83_FILTER limits the run to files whose name contains the given text.
+//
+// The test is skipped when GEBB_SM83_TESTS isn't set.
+
+use gb_core::cpu::bus::Bus;
+use gb_core::cpu::Cpu;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+
+// A read or write on the bus: the address, the value and whether it was a write.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Access {
+    addr: u16,
+    value: u8,
+    write: bool,
+}
+
+impl std::fmt::Display for Access {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} {:04X}={:02X}", if self.write { "w" } else { "r" }, self.addr, self.value)
+    }
+}
+
+struct FlatBus {
+    memory: Vec<u8>,
+    // Every read and write since the test started, in order. Reads only get &self.
+    accesses: RefCell<Vec<Access>>,
+}
+
+impl Bus for FlatBus {
+    fn read_byte(&self, loc: u16) -> u8 {
+        let value = self.memory[loc as usize];
+        self.accesses.borrow_mut().push(Access { addr: loc, value, write: false });
+        value
+    }
+
+    fn write_byte(&mut self, loc: u16, data: u8) {
+        self.accesses.get_mut().push(Access { addr: loc, value: data, write: true });
+        self.memory[loc as usize] = data;
+    }
+
+    fn peek(&self, loc: u16) -> u8 {
+        self.memory[loc as usize]
+    }
+}
+
+fn field(state: &Value, name: &str) -> u16 {
+    state[name].as_u64().unwrap_or(0) as u16
+}
+
+fn ram(state: &Value) -> Vec<(u16, u8)> {
+    let Some(entries) = state["ram"].as_array() else { return Vec::new() };
+    entries.iter().map(|e| (e[0].as_u64().unwrap() as u16, e[1].as_u64().unwrap() as u8)).collect()
+}
+
+// The reads and writes in a test's cycles, which are [address, value, pins] with pins like "r-m"
+// for a read, "-wm" for a write or "---" for a cycle without a memory access.
+fn accesses(test: &Value) -> Vec<Access> {
+    let Some(cycles) = test["cycles"].as_array() else { return Vec::new() };
+    cycles
+        .iter()
+        .filter_map(|cycle| {
+            let pins = cycle[2].as_str()?;
+            let write = pins.contains('w');
+            (write || pins.contains('r')).then(|| Access {
+                addr: cycle[0].as_u64().unwrap() as u16,
+                value: cycle[1].as_u64().unwrap() as u8,
+                write,
+            })
+        })
+        .collect()
+}
+
+fn list(accesses: &[Access]) -> String {
+    accesses.iter().map(Access::to_string).collect::<Vec<_>>().join(", ")
+}
+
+fn setup(state: &Value) -> Cpu<FlatBus> {
+    let mut cpu = Cpu::with_bus(FlatBus { memory: vec![0; 0x10000], accesses: RefCell::new(Vec::new()) });
+    let r = cpu.registers_mut();
+    r.a = field(state, "a") as u8;
+    r.b = field(state, "b") as u8;
+    r.c = field(state, "c") as u8;
+    r.d = field(state, "d") as u8;
+    r.e = field(state, "e") as u8;
+    r.f = field(state, "f") as u8;
+    r.h = field(state, "h") as u8;
+    r.l = field(state, "l") as u8;
+    cpu.set_pc(field(state, "pc"));
+    cpu.set_sp(field(state, "sp"));
+    cpu.set_ime(field(state, "ime") != 0);
+    for (loc, value) in ram(state) {
+        cpu.mmu.memory[loc as usize] = value;
+    }
+    cpu
+}
+
+// Returns a description of every difference from the expected final state.
+fn check(cpu: &Cpu<FlatBus>, cycles: u32, test: &Value) -> Vec<String> {
+    let mut errors = Vec::new();
+    let (found, expected) = (cpu.mmu.accesses.borrow(), accesses(test));
+    if *found != expected {
+        errors.push(format!("bus activity was [{}], expected [{}]", list(&found), list(&expected)));
+    }
+    let expected = &test["final"];
+    let r = cpu.registers();
+    let actual = [
+        ("a", r.a as u16),
+        ("b", r.b as u16),
+        ("c", r.c as u16),
+        ("d", r.d as u16),
+        ("e", r.e as u16),
+        ("f", r.f as u16),
+        ("h", r.h as u16),
+        ("l", r.l as u16),
+        ("pc", cpu.pc()),
+        ("sp", cpu.sp()),
+        ("ime", cpu.ime() as u16),
+    ];
+    for (name, value) in actual {
+        if expected.get(name).is_some() && value != field(expected, name) {
+            errors.push(format!("{} is {:#06x}, expected {:#06x}", name, value, field(expected, name)));
+        }
+    }
+    for (loc, value) in ram(expected) {
+        let found = cpu.mmu.peek(loc);
+        if found != value {
+            errors.push(format!("[{:#06x}] is {:#04x}, expected {:#04x}", loc, found, value));
+        }
+    }
+    let expected_cycles = test["cycles"].as_array().map_or(0, |c| c.len()) as u32;
+    if cycles != expected_cycles {
+        errors.push(format!("took {} cycles, expected {}", cycles, expected_cycles));
+    }
+    errors
+}
+
+fn run(test: &Value) -> Vec<String> {
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut cpu = setup(&test["initial"]);
+        match cpu.step() {
+            Ok(cycles) => check(&cpu, cycles, test),
+            Err(e) => vec![e.to_string()],
+        }
+    }));
+    result.unwrap_or_else(|e| {
+        let message = e
+            .downcast_ref::<String>()
+            .cloned()
+            .or_else(|| e.downcast_ref::<&str>().map(|s| s.to_string()))
+            .unwrap_or_default();
+        vec![format!("panicked: {}", message)]
+    })
+}
+
+#[test]
+fn sm83() {
+    let Ok(dir) = env::var("GEBB_SM83_TESTS") else {
+        eprintln!("GEBB_SM83_TESTS is not set, skipping SM83 test vectors");
+        return;
+    };
+    let filter = env::var("GEBB_SM83_FILTER").unwrap_or_default();
+
+    let mut files: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|e| e == "json"))
+        .filter(|p| p.file_name().unwrap().to_string_lossy().contains(&filter))
+        .collect();
+    files.sort();
+    assert!(!files.is_empty(), "No test vectors found in {}", dir);
+
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let mut report = String::new();
+    let mut failed_files = 0;
+    for path in &files {
+        let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+        let tests: Vec<Value> = serde_json::from_slice(&fs::read(path).unwrap()).unwrap();
+        // Only the first failure of each opcode is shown, the rest tend to repeat it.
+        let mut first_failure = None;
+        let mut failed = 0;
+        for test in &tests {
+            let errors = run(test);
+            if !errors.is_empty() {
+                failed += 1;
+                let name = test["name"].as_str().unwrap_or("");
+                first_failure.get_or_insert_with(|| format!("{}: {}", name, errors.join(", ")));
+            }
+        }
+        if let Some(failure) = first_failure {
+            failed_files += 1;
+            report += &format!("{:6}  {}/{} failed, first: {}\n", name, failed, tests.len(), failure);
+        }
+    }
+    panic::set_hook(hook);
+    report += &format!("{}/{} opcodes passed\n", files.len() - failed_files, files.len());
+    println!("{}", report);
+
+    assert_eq!(failed_files, 0, "\n{}", report);
+}
@@ -0,0 +1,164 @@
+// Runs the Blargg and Mooneye test ROM suites found under the directory named by GEBB_TEST_ROMS.
+//
+// Blargg ROMs (cpu_instrs, instr_timing, mem_timing) report "Passed" or "Failed" over the serial
+// port. Mooneye acceptance ROMs execute LD B,B when done, with B, C, D, E, H, L set to the
+// Fibonacci numbers 3, 5, 8, 13, 21, 34 on success and to 0x42 on failure.
+//
+// GEBB_TEST_ROM_FILTER limits the run to ROMs whose path contains the given text, and
+// GEBB_TEST_ROM_CYCLES overrides how many cycles each ROM gets before it times out.
+//
+// The test is skipped when GEBB_TEST_ROMS isn't set.
+
+use gb_core::cpu::Cpu;
+use gb_core::serial::StdoutLink;
+use std::env;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+const DEFAULT_CYCLES: u64 = 4_194_304 * 60;
+const LD_B_B: u8 = 0x40;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Suite {
+    Blargg,
+    Mooneye,
+}
+
+#[derive(PartialEq)]
+enum Outcome {
+    Pass,
+    Fail(String),
+    Timeout,
+    Panic(String),
+}
+
+fn find_roms(dir: &Path, suite: Option<Suite>, out: &mut Vec<(Suite, PathBuf)>) {
+    let Ok(entries) = fs::read_dir(dir) else { return };
+    let mut entries: Vec<_> = entries.flatten().map(|e| e.path()).collect();
+    entries.sort();
+    for path in entries {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        if path.is_dir() {
+            let suite = match name.as_str() {
+                "cpu_instrs" | "instr_timing" | "mem_timing" => Some(Suite::Blargg),
+                "acceptance" => Some(Suite::Mooneye),
+                _ => suite,
+            };
+            find_roms(&path, suite, out);
+        } else if let Some(suite) = suite.filter(|_| name.ends_with(".gb")) {
+            out.push((suite, path));
+        }
+    }
+}
+
+fn run_blargg(gb: &mut Cpu, cycles: u64) -> Outcome {
+    let serial = StdoutLink::new(false);
+    let output = serial.output();
+    gb.set_serial_link(Box::new(serial));
+
+    let mut elapsed = 0;
+    while elapsed < cycles {
+        match gb.run_frame() {
+            Ok(cycles) => elapsed += cycles as u64,
+            Err(e) => return Outcome::Fail(e.to_string()),
+        }
+        let text = String::from_utf8_lossy(&output.lock().unwrap()).into_owned();
+        if text.contains("Passed") {
+            return Outcome::Pass;
+        }
+        if text.contains("Failed") {
+            return Outcome::Fail(text.trim().lines().last().unwrap_or("").to_string());
+        }
+    }
+    Outcome::Timeout
+}
+
+fn run_mooneye(gb: &mut Cpu, cycles: u64) -> Outcome {
+    let mut elapsed = 0;
+    while elapsed < cycles {
+        let breakpoint = gb.mmu.read_byte(gb.pc()) == LD_B_B;
+        match gb.do_cycle() {
+            Ok(cycles) => elapsed += cycles as u64,
+            Err(e) => return Outcome::Fail(e.to_string()),
+        }
+        if breakpoint {
+            let r = gb.registers();
+            let result = [r.b, r.c, r.d, r.e, r.h, r.l];
+            return if result == [3, 5, 8, 13, 21, 34] {
+                Outcome::Pass
+            } else {
+                Outcome::Fail(format!("registers {:02X?}", result))
+            };
+        }
+    }
+    Outcome::Timeout
+}
+
+fn run(suite: Suite, rom: &[u8], cycles: u64) -> Outcome {
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut gb = Cpu::new();
+        if let Err(e) = gb.load(rom) {
+            return Outcome::Fail(e.to_string());
+        }
+        match suite {
+            Suite::Blargg => run_blargg(&mut gb, cycles),
+            Suite::Mooneye => run_mooneye(&mut gb, cycles),
+        }
+    }));
+    result.unwrap_or_else(|e| {
+        let message = e
+            .downcast_ref::<String>()
+            .cloned()
+            .or_else(|| e.downcast_ref::<&str>().map(|s| s.to_string()))
+            .unwrap_or_default();
+        Outcome::Panic(message)
+    })
+}
+
+#[test]
+fn test_roms() {
+    let Ok(dir) = env::var("GEBB_TEST_ROMS") else {
+        eprintln!("GEBB_TEST_ROMS is not set, skipping test ROMs");
+        return;
+    };
+    let filter = env::var("GEBB_TEST_ROM_FILTER").unwrap_or_default();
+    let cycles = env::var("GEBB_TEST_ROM_CYCLES").ok().and_then(|c| c.parse().ok()).unwrap_or(DEFAULT_CYCLES);
+
+    let mut roms = Vec::new();
+    find_roms(Path::new(&dir), None, &mut roms);
+    roms.retain(|(_, path)| path.to_string_lossy().contains(&filter));
+    assert!(!roms.is_empty(), "No test ROMs found in {}", dir);
+
+    // Panics are caught and reported per ROM, so keep the default hook from printing each one.
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let results: Vec<_> = roms
+        .iter()
+        .map(|(suite, path)| {
+            let outcome = match fs::read(path) {
+                Ok(rom) => run(*suite, &rom, cycles),
+                Err(e) => Outcome::Panic(e.to_string()),
+            };
+            (path.strip_prefix(&dir).unwrap_or(path).display().to_string(), outcome)
+        })
+        .collect();
+    panic::set_hook(hook);
+
+    let width = results.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    let mut table = String::new();
+    for (name, outcome) in &results {
+        let result = match outcome {
+            Outcome::Pass => String::from("pass"),
+            Outcome::Fail(detail) => format!("FAIL  {}", detail),
+            Outcome::Timeout => String::from("TIMEOUT"),
+            Outcome::Panic(detail) => format!("PANIC {}", detail),
+        };
+        table += &format!("{:width$}  {}\n", name, result, width = width);
+    }
+    let passed = results.iter().filter(|(_, o)| *o == Outcome::Pass).count();
+    table += &format!("{}/{} passed\n", passed, results.len());
+    println!("{}", table);
+
+    assert_eq!(passed, results.len(), "\n{}", table);
+}
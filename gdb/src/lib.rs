@@ -0,0 +1,409 @@
+use gb_core::cpu::Cpu;
+use gb_core::debugger::{Access, Breakpoint, Debugger, Stop, Watchpoint};
+use gdbstub::arch::{Arch, RegId, Registers};
+use gdbstub::common::Signal;
+use gdbstub::conn::ConnectionExt;
+use gdbstub::stub::{run_blocking, DisconnectReason, GdbStub, SingleThreadStopReason};
+use gdbstub::target::ext::base::single_register_access::{SingleRegisterAccess, SingleRegisterAccessOps};
+use gdbstub::target::ext::base::singlethread::{
+    SingleThreadBase, SingleThreadResume, SingleThreadResumeOps, SingleThreadSingleStep, SingleThreadSingleStepOps,
+};
+use gdbstub::target::ext::base::BaseOps;
+use gdbstub::target::ext::breakpoints::{
+    Breakpoints, BreakpointsOps, HwWatchpoint, HwWatchpointOps, SwBreakpoint, SwBreakpointOps, WatchKind,
+};
+use gdbstub::target::{Target, TargetError, TargetResult};
+use std::net::TcpStream;
+use std::num::NonZeroUsize;
+
+// Registers are sent as AF, BC, DE, HL, SP and PC, 16 bits each in little endian. That's also the
+// start of GDB's z80 register layout, so a GDB built with z80 support can be used as is.
+pub enum Sm83 {}
+
+impl Arch for Sm83 {
+    type Usize = u16;
+    type Registers = Sm83Registers;
+    type BreakpointKind = usize;
+    type RegId = Sm83RegId;
+
+    fn target_description_xml() -> Option<&'static str> {
+        Some(r#"<target version="1.0"><architecture>z80</architecture></target>"#)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Sm83Registers {
+    pub af: u16,
+    pub bc: u16,
+    pub de: u16,
+    pub hl: u16,
+    pub sp: u16,
+    pub pc: u16,
+}
+
+impl Sm83Registers {
+    fn all(&self) -> [u16; 6] {
+        [self.af, self.bc, self.de, self.hl, self.sp, self.pc]
+    }
+}
+
+impl Registers for Sm83Registers {
+    type ProgramCounter = u16;
+
+    fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    fn gdb_serialize(&self, mut write_byte: impl FnMut(Option<u8>)) {
+        for value in self.all() {
+            for byte in value.to_le_bytes() {
+                write_byte(Some(byte));
+            }
+        }
+    }
+
+    fn gdb_deserialize(&mut self, bytes: &[u8]) -> Result<(), ()> {
+        if bytes.len() < 12 {
+            return Err(());
+        }
+        let mut values = bytes.chunks_exact(2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+        for register in [&mut self.af, &mut self.bc, &mut self.de, &mut self.hl, &mut self.sp, &mut self.pc] {
+            *register = values.next().ok_or(())?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sm83RegId {
+    Af,
+    Bc,
+    De,
+    Hl,
+    Sp,
+    Pc,
+}
+
+impl RegId for Sm83RegId {
+    fn from_raw_id(id: usize) -> Option<(Self, Option<NonZeroUsize>)> {
+        let reg = match id {
+            0 => Self::Af,
+            1 => Self::Bc,
+            2 => Self::De,
+            3 => Self::Hl,
+            4 => Self::Sp,
+            5 => Self::Pc,
+            _ => return None,
+        };
+        Some((reg, NonZeroUsize::new(2)))
+    }
+}
+
+fn access(kind: WatchKind) -> Access {
+    match kind {
+        WatchKind::Write => Access::Write,
+        WatchKind::Read => Access::Read,
+        WatchKind::ReadWrite => Access::Any,
+    }
+}
+
+// A Cpu being debugged over the GDB remote serial protocol. It only runs while GDB has asked it
+// to continue or step.
+pub struct GdbTarget {
+    pub cpu: Cpu,
+    debugger: Debugger,
+}
+
+impl GdbTarget {
+    pub fn new(cpu: Cpu) -> Self {
+        Self { cpu, debugger: Debugger::new() }
+    }
+
+    fn stop_reason(&self, stop: Stop) -> SingleThreadStopReason<u16> {
+        match stop {
+            Stop::Breakpoint(_) => SingleThreadStopReason::SwBreak(()),
+            Stop::Step => SingleThreadStopReason::DoneStep,
+            Stop::Watchpoint(hit) => {
+                // Report the kind GDB asked for, so it recognises its own watchpoint.
+                let kind = self
+                    .debugger
+                    .watchpoints()
+                    .iter()
+                    .find(|w| w.addr == hit.addr && w.access != if hit.write { Access::Read } else { Access::Write })
+                    .map_or(WatchKind::Write, |w| match w.access {
+                        Access::Write => WatchKind::Write,
+                        Access::Read => WatchKind::Read,
+                        Access::Any => WatchKind::ReadWrite,
+                    });
+                SingleThreadStopReason::Watch { tid: (), kind, addr: hit.addr }
+            }
+            Stop::Frame(_) | Stop::Divergence => SingleThreadStopReason::Signal(Signal::SIGTRAP),
+            Stop::Error(_) => SingleThreadStopReason::Signal(Signal::SIGILL),
+        }
+    }
+}
+
+impl Target for GdbTarget {
+    type Arch = Sm83;
+    type Error = &'static str;
+
+    #[inline(always)]
+    fn base_ops(&mut self) -> BaseOps<'_, Sm83, &'static str> {
+        BaseOps::SingleThread(self)
+    }
+
+    #[inline(always)]
+    fn support_breakpoints(&mut self) -> Option<BreakpointsOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl SingleThreadBase for GdbTarget {
+    fn read_registers(&mut self, regs: &mut Sm83Registers) -> TargetResult<(), Self> {
+        let r = self.cpu.registers();
+        *regs = Sm83Registers {
+            af: r.get_af(),
+            bc: r.get_bc(),
+            de: r.get_de(),
+            hl: r.get_hl(),
+            sp: self.cpu.sp(),
+            pc: self.cpu.pc(),
+        };
+        Ok(())
+    }
+
+    fn write_registers(&mut self, regs: &Sm83Registers) -> TargetResult<(), Self> {
+        let r = self.cpu.registers_mut();
+        r.set_af(regs.af & 0xFFF0);
+        r.set_bc(regs.bc);
+        r.set_de(regs.de);
+        r.set_hl(regs.hl);
+        self.cpu.set_sp(regs.sp);
+        self.cpu.set_pc(regs.pc);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn support_single_register_access(&mut self) -> Option<SingleRegisterAccessOps<'_, (), Self>> {
+        Some(self)
+    }
+
+    // Memory is accessed with peek and poke, so GDB can't trigger watchpoints or switch banks.
+    fn read_addrs(&mut self, start_addr: u16, data: &mut [u8]) -> TargetResult<usize, Self> {
+        let mut count = 0;
+        for (addr, value) in (start_addr..=0xFFFF).zip(data.iter_mut()) {
+            *value = self.cpu.mmu.peek(addr);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn write_addrs(&mut self, start_addr: u16, data: &[u8]) -> TargetResult<(), Self> {
+        if start_addr as usize + data.len() > 0x10000 {
+            return Err(TargetError::NonFatal);
+        }
+        for (addr, &value) in (start_addr..).zip(data) {
+            self.cpu.mmu.poke(addr, value);
+        }
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn support_resume(&mut self) -> Option<SingleThreadResumeOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl SingleRegisterAccess<()> for GdbTarget {
+    fn read_register(&mut self, _tid: (), reg_id: Sm83RegId, buf: &mut [u8]) -> TargetResult<usize, Self> {
+        let mut regs = Sm83Registers::default();
+        self.read_registers(&mut regs)?;
+        buf[..2].copy_from_slice(&regs.all()[reg_id as usize].to_le_bytes());
+        Ok(2)
+    }
+
+    fn write_register(&mut self, _tid: (), reg_id: Sm83RegId, val: &[u8]) -> TargetResult<(), Self> {
+        let value = u16::from_le_bytes(val.try_into().map_err(|_| TargetError::NonFatal)?);
+        let r = self.cpu.registers_mut();
+        match reg_id {
+            Sm83RegId::Af => r.set_af(value & 0xFFF0),
+            Sm83RegId::Bc => r.set_bc(value),
+            Sm83RegId::De => r.set_de(value),
+            Sm83RegId::Hl => r.set_hl(value),
+            Sm83RegId::Sp => self.cpu.set_sp(value),
+            Sm83RegId::Pc => self.cpu.set_pc(value),
+        }
+        Ok(())
+    }
+}
+
+impl SingleThreadResume for GdbTarget {
+    fn resume(&mut self, signal: Option<Signal>) -> Result<(), &'static str> {
+        if signal.is_some() {
+            return Err("Signals aren't supported");
+        }
+        self.debugger.cancel();
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn support_single_step(&mut self) -> Option<SingleThreadSingleStepOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl SingleThreadSingleStep for GdbTarget {
+    fn step(&mut self, signal: Option<Signal>) -> Result<(), &'static str> {
+        if signal.is_some() {
+            return Err("Signals aren't supported");
+        }
+        self.debugger.step_into();
+        Ok(())
+    }
+}
+
+impl Breakpoints for GdbTarget {
+    #[inline(always)]
+    fn support_sw_breakpoint(&mut self) -> Option<SwBreakpointOps<'_, Self>> {
+        Some(self)
+    }
+
+    #[inline(always)]
+    fn support_hw_watchpoint(&mut self) -> Option<HwWatchpointOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl SwBreakpoint for GdbTarget {
+    fn add_sw_breakpoint(&mut self, addr: u16, _kind: usize) -> TargetResult<bool, Self> {
+        self.debugger.add_breakpoint(Breakpoint::at(addr));
+        Ok(true)
+    }
+
+    fn remove_sw_breakpoint(&mut self, addr: u16, _kind: usize) -> TargetResult<bool, Self> {
+        Ok(self.debugger.remove_breakpoint(Breakpoint::at(addr)))
+    }
+}
+
+impl HwWatchpoint for GdbTarget {
+    fn add_hw_watchpoint(&mut self, addr: u16, len: u16, kind: WatchKind) -> TargetResult<bool, Self> {
+        for offset in 0..len {
+            let addr = addr.wrapping_add(offset);
+            self.debugger.add_watchpoint(Watchpoint { addr, access: access(kind), value: None });
+        }
+        Ok(true)
+    }
+
+    fn remove_hw_watchpoint(&mut self, addr: u16, len: u16, kind: WatchKind) -> TargetResult<bool, Self> {
+        for offset in 0..len {
+            let watchpoint = Watchpoint { addr: addr.wrapping_add(offset), access: access(kind), value: None };
+            match self.debugger.watchpoints().iter().position(|w| *w == watchpoint) {
+                Some(index) => self.debugger.remove_watchpoint(index),
+                None => return Ok(false),
+            };
+        }
+        Ok(true)
+    }
+}
+
+enum EventLoop {}
+
+impl run_blocking::BlockingEventLoop for EventLoop {
+    type Target = GdbTarget;
+    type Connection = TcpStream;
+    type StopReason = SingleThreadStopReason<u16>;
+
+    #[allow(clippy::type_complexity)]
+    fn wait_for_stop_reason(
+        target: &mut GdbTarget,
+        conn: &mut TcpStream,
+    ) -> Result<
+        run_blocking::Event<SingleThreadStopReason<u16>>,
+        run_blocking::WaitForStopReasonError<&'static str, std::io::Error>,
+    > {
+        // Run a frame at a time, checking in between whether GDB has sent anything (usually an
+        // interrupt from Ctrl-C).
+        loop {
+            if conn.peek().map(|b| b.is_some()).unwrap_or(true) {
+                let byte = conn.read().map_err(run_blocking::WaitForStopReasonError::Connection)?;
+                return Ok(run_blocking::Event::IncomingData(byte));
+            }
+            if let Some(stop) = target.debugger.run_frame(&mut target.cpu) {
+                return Ok(run_blocking::Event::TargetStopped(target.stop_reason(stop)));
+            }
+        }
+    }
+
+    fn on_interrupt(_target: &mut GdbTarget) -> Result<Option<SingleThreadStopReason<u16>>, &'static str> {
+        Ok(Some(SingleThreadStopReason::Signal(Signal::SIGINT)))
+    }
+}
+
+// Serves one GDB session over `stream` until the client detaches, kills the target or goes away.
+pub fn serve(target: &mut GdbTarget, stream: TcpStream) -> Result<DisconnectReason, String> {
+    GdbStub::new(stream).run_blocking::<EventLoop>(target).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use gb_core::test_util::{spawn_with_stack, test_cpu};
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    // Sends a packet and returns the reply, acknowledging it like GDB does.
+    fn request(stream: &mut TcpStream, data: &str) -> String {
+        let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        write!(stream, "${}#{:02x}", data, checksum).unwrap();
+        let mut reply = Vec::new();
+        let mut byte = [0];
+        loop {
+            stream.read_exact(&mut byte).unwrap();
+            match byte[0] {
+                b'+' if reply.is_empty() => continue,
+                b'#' => break,
+                b => reply.push(b),
+            }
+        }
+        stream.read_exact(&mut [0; 2]).unwrap();
+        stream.write_all(b"+").unwrap();
+        String::from_utf8(reply).unwrap().trim_start_matches('$').to_string()
+    }
+
+    #[test]
+    fn scripted_session() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = spawn_with_stack(move || {
+            let mut target = GdbTarget::new(test_cpu());
+            let (stream, _) = listener.accept().unwrap();
+            serve(&mut target, stream)
+        });
+
+        let mut gdb = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        assert!(request(&mut gdb, "qSupported:swbreak+;hwbreak+").contains("PacketSize"));
+        assert!(request(&mut gdb, "?").contains("05"));
+        assert_eq!(request(&mut gdb, "g"), "b0111300d8004d01feff0001");
+        assert_eq!(request(&mut gdb, "m100,3"), "cd5001");
+
+        assert_eq!(request(&mut gdb, "Z0,150,1"), "OK");
+        assert!(request(&mut gdb, "c").contains("swbreak"));
+        assert_eq!(request(&mut gdb, "p5"), "5001");
+        assert_eq!(request(&mut gdb, "s"), "S05");
+        assert_eq!(request(&mut gdb, "p5"), "5101");
+        assert_eq!(request(&mut gdb, "p0"), "1012");
+
+        assert_eq!(request(&mut gdb, "P0=00ab"), "OK");
+        assert_eq!(request(&mut gdb, "Mc000,2:4243"), "OK");
+        assert_eq!(request(&mut gdb, "mc000,2"), "4243");
+        assert_eq!(request(&mut gdb, "me000,2"), "4243");
+        assert_eq!(request(&mut gdb, "M200,1:76"), "OK");
+        assert_eq!(request(&mut gdb, "m200,1"), "76");
+        assert_eq!(request(&mut gdb, "Z2,c000,1"), "OK");
+        assert!(request(&mut gdb, "c").contains("watch:c000"));
+        assert_eq!(request(&mut gdb, "mc000,1"), "ab");
+
+        assert_eq!(request(&mut gdb, "D"), "OK");
+        assert!(matches!(server.join().unwrap(), Ok(DisconnectReason::Disconnect)));
+    }
+}
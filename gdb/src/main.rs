@@ -0,0 +1,85 @@
+use gb_core::cpu::Cpu;
+use gdb::GdbTarget;
+use gdbstub::stub::DisconnectReason;
+use std::env;
+use std::fs;
+use std::net::TcpListener;
+use std::process::ExitCode;
+
+const USAGE: &str = "Usage: gebb-gdb ROM [--port N]
+
+Loads ROM and waits for GDB to connect on 127.0.0.1, port 2345 by default. The game only runs while
+GDB continues or steps it, and the server keeps accepting new connections until GDB kills it.
+
+Connect with a GDB built with z80 support:
+  (gdb) set architecture z80
+  (gdb) target remote :2345";
+
+fn main() -> ExitCode {
+    let mut args = env::args().skip(1);
+    let mut rom_path = None;
+    let mut port = 2345;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--port" => match args.next().and_then(|n| n.parse::<u16>().ok()) {
+                Some(n) => port = n,
+                None => {
+                    eprintln!("Invalid port\n\n{}", USAGE);
+                    return ExitCode::from(2);
+                }
+            },
+            "-h" | "--help" => {
+                eprintln!("{}", USAGE);
+                return ExitCode::from(2);
+            }
+            _ => rom_path = Some(arg),
+        }
+    }
+    let Some(rom_path) = rom_path else {
+        eprintln!("Missing ROM path\n\n{}", USAGE);
+        return ExitCode::from(2);
+    };
+
+    let rom = match fs::read(&rom_path) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("Unable to open {}: {}", rom_path, e);
+            return ExitCode::from(2);
+        }
+    };
+    let mut cpu = Cpu::new();
+    if let Err(e) = cpu.load(&rom) {
+        eprintln!("Unable to load {}: {}", rom_path, e);
+        return ExitCode::from(2);
+    }
+    let mut target = GdbTarget::new(cpu);
+
+    let listener = match TcpListener::bind(("127.0.0.1", port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Unable to listen on port {}: {}", port, e);
+            return ExitCode::from(2);
+        }
+    };
+    loop {
+        println!("Waiting for GDB on 127.0.0.1:{}", port);
+        let stream = match listener.accept() {
+            Ok((stream, addr)) => {
+                println!("GDB connected from {}", addr);
+                stream
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                return ExitCode::from(2);
+            }
+        };
+        match gdb::serve(&mut target, stream) {
+            Ok(DisconnectReason::Kill) => {
+                println!("Killed by GDB");
+                return ExitCode::SUCCESS;
+            }
+            Ok(_) => println!("GDB disconnected"),
+            Err(e) => eprintln!("GDB session ended with an error: {}", e),
+        }
+    }
+}
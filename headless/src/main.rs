@@ -0,0 +1,252 @@
+use gb_core::cpu::{Cpu, TICKS_PER_FRAME};
+use gb_core::image;
+use gb_core::memview;
+use gb_core::serial::StdoutLink;
+use gb_core::symbols::Symbols;
+use gb_core::trace::Tracer;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::ExitCode;
+
+const USAGE: &str = "Usage: gebb-headless ROM [options]
+
+Runs ROM without a window until a limit or condition is reached.
+
+Options:
+  --frames N           Stop after N frames (default 3600 when no other limit is given)
+  --cycles N           Stop after N clock cycles
+  --pass TEXT          Stop with success once the serial output contains TEXT
+  --fail TEXT          Stop with failure once the serial output contains TEXT
+  --screenshot PATH    Save the final frame as a PNG
+  --serial-echo        Print serial output as it arrives
+  --trace PATH         Log every instruction in the Gameboy Doctor format
+  --trace-diff PATH    Fail at the first instruction that doesn't match a reference log
+  --doctor             Make LY always read 0x90, as Gameboy Doctor reference logs expect
+  --symbols PATH       Name the location of a trace divergence using an RGBDS .sym file (by
+                       default the .sym file next to ROM, if there is one)
+  --dump ADDR[:LEN]    Print a hex dump of LEN bytes (default 0x100) from ADDR at the end, both in
+                       hex. Can be given more than once
+
+Exit status: 0 on success or when a limit is reached with no --pass given, 1 on failure or when a
+limit is reached before --pass matched, 2 on usage or I/O errors.";
+
+struct Options {
+    rom: String,
+    frames: Option<u64>,
+    cycles: Option<u64>,
+    pass: Option<String>,
+    fail: Option<String>,
+    screenshot: Option<String>,
+    serial_echo: bool,
+    trace: Option<String>,
+    trace_diff: Option<String>,
+    doctor: bool,
+    symbols: Option<String>,
+    dumps: Vec<(u16, usize)>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut args = env::args().skip(1);
+    let mut options = Options {
+        rom: String::new(),
+        frames: None,
+        cycles: None,
+        pass: None,
+        fail: None,
+        screenshot: None,
+        serial_echo: false,
+        trace: None,
+        trace_diff: None,
+        doctor: false,
+        symbols: None,
+        dumps: Vec::new(),
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("Missing value for {}", arg));
+        match arg.as_str() {
+            "--frames" => options.frames = Some(value()?.parse().map_err(|_| "Invalid frame count")?),
+            "--cycles" => options.cycles = Some(value()?.parse().map_err(|_| "Invalid cycle count")?),
+            "--pass" => options.pass = Some(value()?),
+            "--fail" => options.fail = Some(value()?),
+            "--screenshot" => options.screenshot = Some(value()?),
+            "--serial-echo" => options.serial_echo = true,
+            "--trace" => options.trace = Some(value()?),
+            "--trace-diff" => options.trace_diff = Some(value()?),
+            "--doctor" => options.doctor = true,
+            "--symbols" => options.symbols = Some(value()?),
+            "--dump" => options.dumps.push(parse_dump(&value()?).ok_or("Invalid memory range")?),
+            "-h" | "--help" => return Err(String::new()),
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ => options.rom = arg,
+        }
+    }
+    if options.rom.is_empty() {
+        return Err(String::from("Missing ROM path"));
+    }
+    if options.frames.is_none() && options.cycles.is_none() {
+        options.frames = Some(3600);
+    }
+    Ok(options)
+}
+
+// ADDR or ADDR:LEN, in hex.
+fn parse_dump(text: &str) -> Option<(u16, usize)> {
+    let hex = |text: &str| usize::from_str_radix(text.trim_start_matches('$').trim_start_matches("0x"), 16).ok();
+    let (addr, len) = match text.split_once(':') {
+        Some((addr, len)) => (hex(addr)?, hex(len)?),
+        None => (hex(text)?, 0x100),
+    };
+    Some((u16::try_from(addr).ok()?, len))
+}
+
+// Whether `text` is in `output`, only looking at matches that end at or after `from`.
+fn contains_from(output: &[u8], text: &str, from: usize) -> bool {
+    if text.is_empty() {
+        return true;
+    }
+    let start = from.saturating_sub(text.len() - 1);
+    output[start..].windows(text.len()).any(|w| w == text.as_bytes())
+}
+
+enum Outcome {
+    Passed,
+    Failed,
+    LimitReached,
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(e) => {
+            if !e.is_empty() {
+                eprintln!("{}\n", e);
+            }
+            eprintln!("{}", USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    let rom = match fs::read(&options.rom) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("Unable to open {}: {}", options.rom, e);
+            return ExitCode::from(2);
+        }
+    };
+
+    let mut gb = Cpu::new();
+    if let Err(e) = gb.load(&rom) {
+        eprintln!("Unable to load {}: {}", options.rom, e);
+        return ExitCode::from(2);
+    }
+    gb.set_doctor_mode(options.doctor);
+    let serial = StdoutLink::new(options.serial_echo);
+    let output = serial.output();
+    gb.set_serial_link(Box::new(serial));
+    if options.trace.is_some() || options.trace_diff.is_some() {
+        let mut tracer =
+            match Tracer::open(options.trace.as_deref().map(Path::new), options.trace_diff.as_deref().map(Path::new)) {
+                Ok(tracer) => tracer,
+                Err(e) => {
+                    eprintln!("Unable to open trace: {}", e);
+                    return ExitCode::from(2);
+                }
+            };
+        let symbols = match &options.symbols {
+            Some(path) => Symbols::load(Path::new(path)).map(Some),
+            None => Symbols::load_for_rom(Path::new(&options.rom)),
+        };
+        match symbols {
+            Ok(symbols) => tracer.set_symbols(symbols),
+            Err(e) => {
+                eprintln!("Unable to load symbols: {}", e);
+                return ExitCode::from(2);
+            }
+        }
+        gb.set_tracer(Some(tracer));
+    }
+
+    let mut frames = 0;
+    let mut cycles = 0;
+    // How much of the serial output has already been searched for --pass and --fail.
+    let mut searched = 0;
+    let outcome = loop {
+        // The end of a --cycles budget is run on its own rather than as a whole frame.
+        let budget = options.cycles.map(|n| n.saturating_sub(cycles)).filter(|&b| b < TICKS_PER_FRAME as u64);
+        let result = match budget {
+            Some(budget) => gb.run_cycles(budget as u32),
+            None => gb.run_frame(),
+        };
+        match result {
+            Ok(ran) => cycles += ran as u64,
+            Err(e) => {
+                eprintln!("{}", e);
+                break Outcome::Failed;
+            }
+        }
+        if budget.is_none() {
+            frames += 1;
+        }
+
+        // run_frame returns as soon as the trace diverges, rather than at the end of the frame.
+        if let Some(divergence) = gb.tracer().and_then(|t| t.divergence()) {
+            eprintln!("{}", divergence);
+            break Outcome::Failed;
+        }
+        let (failed, passed) = {
+            let output = output.lock().unwrap();
+            let found = |text: &Option<String>| text.as_ref().is_some_and(|text| contains_from(&output, text, searched));
+            let found = (found(&options.fail), found(&options.pass));
+            searched = output.len();
+            found
+        };
+        if failed {
+            break Outcome::Failed;
+        }
+        if passed {
+            break Outcome::Passed;
+        }
+        if options.frames.is_some_and(|n| frames >= n) || options.cycles.is_some_and(|n| cycles >= n) {
+            break Outcome::LimitReached;
+        }
+    };
+
+    if !options.serial_echo {
+        let output = output.lock().unwrap();
+        if !output.is_empty() {
+            println!("{}", String::from_utf8_lossy(&output));
+        }
+    }
+
+    for &(addr, len) in &options.dumps {
+        for row in memview::dump(&gb.mmu, addr, len) {
+            println!("{}", row);
+        }
+    }
+
+    if let Some(mut tracer) = gb.take_tracer() {
+        if let Some(e) = tracer.error() {
+            eprintln!("Trace stopped early: {}", e);
+        }
+        if let Err(e) = tracer.flush() {
+            eprintln!("Unable to write trace: {}", e);
+        }
+    }
+
+    if let Some(path) = &options.screenshot {
+        if let Err(e) = image::save_png(path, 160, 144, gb.get_display()) {
+            eprintln!("Unable to save screenshot {}: {}", path, e);
+            return ExitCode::from(2);
+        }
+    }
+
+    let (status, message) = match outcome {
+        Outcome::Passed => (0, "passed"),
+        Outcome::Failed => (1, "failed"),
+        Outcome::LimitReached if options.pass.is_some() => (1, "timed out"),
+        Outcome::LimitReached => (0, "limit reached"),
+    };
+    eprintln!("{} after {} frames ({} cycles)", message, frames, cycles);
+    ExitCode::from(status)
+}